@@ -0,0 +1,34 @@
+// Compare JSON ingestion paths: `Vec<Bar>` + `bars_to_dataframe` vs direct columnar load
+//
+// cargo run --release --example ingest_bench -- [path] [iterations]
+
+use std::time::{Duration, Instant};
+use anyhow::Result;
+use DataLoader::{loader, resampler};
+
+fn time<F: FnMut() -> Result<usize>>(iterations: u32, mut f: F) -> Result<(Duration, usize)> {
+    let mut rows = 0;
+    let start = Instant::now();
+    for _ in 0..iterations {
+        rows = f()?;
+    }
+    Ok((start.elapsed() / iterations, rows))
+}
+
+fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    let path = args.next().unwrap_or_else(|| "raw_data/sample.json".to_string());
+    let iterations: u32 = args.next().map(|n| n.parse()).transpose()?.unwrap_or(20);
+
+    let (via_bars, rows) = time(iterations, || {
+        let bars = loader::load_bars_from_file(&path)?;
+        Ok(resampler::bars_to_dataframe(&bars)?.height())
+    })?;
+    let (direct, _) = time(iterations, || Ok(loader::load_dataframe_from_file(&path)?.height()))?;
+
+    println!("{path}: {rows} rows, {iterations} iterations");
+    println!("  Vec<Bar> + bars_to_dataframe: {via_bars:?} per load");
+    println!("  load_dataframe_from_file:     {direct:?} per load");
+    println!("  speedup: {:.2}x", via_bars.as_secs_f64() / direct.as_secs_f64());
+    Ok(())
+}
@@ -0,0 +1,276 @@
+// Read vendor / broker CSV exports into `Bar`s
+//
+// Column names, timestamp encoding and price units vary by source, so they are
+// described by a `CsvConfig`. Output uses the same fixed-point (1e-9) prices and
+// nanosecond timestamps as the JSON and DBN loaders.
+
+use anyhow::{bail, ensure, Context, Result};
+use chrono::{DateTime, NaiveDateTime, TimeZone};
+use chrono_tz::Tz;
+use serde::Deserialize;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::loader::{open_raw, Bar};
+use crate::prices::FIXED_POINT_SCALE;
+
+/// Naive formats tried for ISO-8601 timestamps without an offset
+const NAIVE_FORMATS: [&str; 4] = ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M"];
+
+/// How the timestamp column is encoded
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimestampFormat {
+    EpochNanos,
+    EpochMillis,
+    EpochSeconds,
+    /// ISO-8601 / RFC 3339; values without an offset are read in `naive_tz`
+    Iso8601 { naive_tz: Tz },
+}
+
+impl FromStr for TimestampFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "epoch-ns" => Ok(Self::EpochNanos),
+            "epoch-ms" => Ok(Self::EpochMillis),
+            "epoch-s" => Ok(Self::EpochSeconds),
+            "iso" => Ok(Self::Iso8601 { naive_tz: Tz::UTC }),
+            other => match other.strip_prefix("iso:") {
+                Some(tz) => Ok(Self::Iso8601 { naive_tz: tz.parse().map_err(|e| anyhow::anyhow!("Unknown time zone '{tz}': {e}"))? }),
+                None => bail!("Unknown timestamp format '{other}' (expected epoch-ns, epoch-ms, epoch-s or iso[:<tz>])"),
+            },
+        }
+    }
+}
+
+/// Header names of the columns to read
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CsvColumns {
+    pub ts: String,
+    pub open: String,
+    pub high: String,
+    pub low: String,
+    pub close: String,
+    pub volume: String,
+    pub instrument_name: Option<String>,
+    pub instrument_id: Option<String>,
+}
+
+impl Default for CsvColumns {
+    fn default() -> Self {
+        Self {
+            ts: "ts".into(),
+            open: "open".into(),
+            high: "high".into(),
+            low: "low".into(),
+            close: "close".into(),
+            volume: "volume".into(),
+            instrument_name: None,
+            instrument_id: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CsvConfig {
+    pub columns: CsvColumns,
+    pub timestamp_format: TimestampFormat,
+    pub price_scale: f64,        // raw value / price_scale = price in dollars (1.0 for decimal prices)
+    pub delimiter: u8,
+    pub instrument_name: String, // used when there is no instrument name column
+    pub instrument_id: u32,      // used when there is no instrument id column
+}
+
+impl Default for CsvConfig {
+    fn default() -> Self {
+        Self {
+            columns: CsvColumns::default(),
+            timestamp_format: TimestampFormat::EpochNanos,
+            price_scale: 1.0,
+            delimiter: b',',
+            instrument_name: String::new(),
+            instrument_id: 0,
+        }
+    }
+}
+
+/// JSON form of a [`CsvConfig`]; every field is optional
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct CsvConfigFile {
+    columns: CsvColumns,
+    timestamp_format: Option<String>, // see `TimestampFormat::from_str`
+    price_scale: Option<f64>,
+    delimiter: Option<char>,
+    instrument_name: String,
+    instrument_id: u32,
+}
+
+/// Load a CSV config from JSON:
+/// `{ "columns": { "ts": "time" }, "timestamp_format": "iso:America/New_York", "price_scale": 1.0, "delimiter": ";" }`
+pub fn load_csv_config<P: AsRef<Path>>(path: P) -> Result<CsvConfig> {
+    let file = File::open(&path)
+        .with_context(|| format!("Failed to open CSV config: {}", path.as_ref().display()))?;
+    let raw: CsvConfigFile = serde_json::from_reader(BufReader::new(file))
+        .with_context(|| format!("Failed to parse CSV config: {}", path.as_ref().display()))?;
+    let defaults = CsvConfig::default();
+    let delimiter = raw.delimiter.map_or(Ok(defaults.delimiter), |c| u8::try_from(c).context("CSV delimiter must be ASCII"))?;
+    let price_scale = raw.price_scale.unwrap_or(defaults.price_scale);
+    ensure!(price_scale > 0.0, "CSV price_scale must be positive, got {price_scale}");
+    Ok(CsvConfig {
+        columns: raw.columns,
+        timestamp_format: raw.timestamp_format.map_or(Ok(defaults.timestamp_format), |f| f.parse())?,
+        price_scale,
+        delimiter,
+        instrument_name: raw.instrument_name,
+        instrument_id: raw.instrument_id,
+    })
+}
+
+/// Parse a timestamp cell into UNIX nanoseconds
+pub fn parse_timestamp(raw: &str, format: TimestampFormat) -> Result<i64> {
+    let raw = raw.trim();
+    let epoch = |mult: i64| -> Result<i64> {
+        if let Ok(i) = raw.parse::<i64>() {
+            return i.checked_mul(mult).with_context(|| format!("Epoch timestamp '{raw}' is out of range"));
+        }
+        let v: f64 = raw.parse().with_context(|| format!("Invalid epoch timestamp '{raw}'"))?;
+        let nanos = (v * mult as f64).round();
+        // i64::MIN and i64::MAX + 1 are both exact powers of two as f64
+        ensure!(nanos >= i64::MIN as f64 && nanos < i64::MAX as f64, "Epoch timestamp '{raw}' is out of range");
+        Ok(nanos as i64)
+    };
+    match format {
+        TimestampFormat::EpochNanos => epoch(1),
+        TimestampFormat::EpochMillis => epoch(1_000_000),
+        TimestampFormat::EpochSeconds => epoch(1_000_000_000),
+        TimestampFormat::Iso8601 { naive_tz } => {
+            let dt = if let Ok(dt) = DateTime::parse_from_rfc3339(raw) {
+                dt.to_utc()
+            } else if let Ok(dt) = DateTime::parse_from_str(raw, "%Y-%m-%d %H:%M:%S%.f%#z") {
+                dt.to_utc()
+            } else {
+                let naive = NAIVE_FORMATS
+                    .iter()
+                    .find_map(|f| NaiveDateTime::parse_from_str(raw, f).ok())
+                    .with_context(|| format!("Invalid ISO-8601 timestamp '{raw}'"))?;
+                naive_tz
+                    .from_local_datetime(&naive)
+                    .single()
+                    .with_context(|| format!("Ambiguous or non-existent local time '{raw}' in {naive_tz}"))?
+                    .to_utc()
+            };
+            dt.timestamp_nanos_opt().with_context(|| format!("Timestamp out of range '{raw}'"))
+        }
+    }
+}
+
+fn column_index(headers: &csv::StringRecord, name: &str) -> Result<usize> {
+    headers
+        .iter()
+        .position(|h| h.trim() == name)
+        .with_context(|| format!("CSV column '{name}' not found"))
+}
+
+/// Bars of a CSV file (optionally `.gz` / `.zst` compressed), parsed row by row
+pub fn csv_bars(path: &Path, config: &CsvConfig) -> Result<impl Iterator<Item = Result<Bar>> + Send + use<>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(config.delimiter)
+        .trim(csv::Trim::All)
+        .from_reader(open_raw(path)?);
+    let headers = reader.headers()?.clone();
+    let c = &config.columns;
+    let ts = column_index(&headers, &c.ts)?;
+    let open = column_index(&headers, &c.open)?;
+    let high = column_index(&headers, &c.high)?;
+    let low = column_index(&headers, &c.low)?;
+    let close = column_index(&headers, &c.close)?;
+    let volume = column_index(&headers, &c.volume)?;
+    let name_col = c.instrument_name.as_deref().map(|n| column_index(&headers, n)).transpose()?;
+    let id_col = c.instrument_id.as_deref().map(|n| column_index(&headers, n)).transpose()?;
+
+    let source = path.display().to_string();
+    let config = config.clone();
+    Ok(reader.into_records().enumerate().map(move |(line, record)| {
+        let record = record.with_context(|| format!("Failed to read CSV row {} in {}", line + 1, source))?;
+        let field = |i: usize| record.get(i).unwrap_or("");
+        let price = |i: usize| -> Result<i64> {
+            let v: f64 = field(i).parse().with_context(|| format!("Invalid price '{}' on row {}", field(i), line + 1))?;
+            Ok((v / config.price_scale * FIXED_POINT_SCALE as f64).round() as i64)
+        };
+        let volume: f64 = field(volume).parse().with_context(|| format!("Invalid volume '{}' on row {}", field(volume), line + 1))?;
+        if volume < 0.0 {
+            bail!("Negative volume on row {}", line + 1);
+        }
+        Ok(Bar {
+            instrument_name: name_col.map_or_else(|| config.instrument_name.clone(), |i| field(i).to_string()),
+            instrument_id: match id_col {
+                Some(i) => field(i).parse().with_context(|| format!("Invalid instrument id on row {}", line + 1))?,
+                None => config.instrument_id,
+            },
+            ts_event: parse_timestamp(field(ts), config.timestamp_format)
+                .with_context(|| format!("Invalid timestamp on row {} in {}", line + 1, source))?,
+            open: price(open)?,
+            high: price(high)?,
+            low: price(low)?,
+            close: price(close)?,
+            volume: volume.round() as u64,
+        })
+    }))
+}
+
+/// Load bars from a CSV file (optionally `.gz` / `.zst` compressed)
+pub fn load_bars_from_csv<P: AsRef<Path>>(path: P, config: &CsvConfig) -> Result<Vec<Bar>> {
+    csv_bars(path.as_ref(), config)?.collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_epoch_millis_csv() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bars.csv");
+        std::fs::write(&path, "ts,open,high,low,close,volume\n1668126900000,85.59,85.60,85.59,85.59,3\n").unwrap();
+        let config = CsvConfig {
+            timestamp_format: TimestampFormat::EpochMillis,
+            instrument_name: "CLF3".into(),
+            ..Default::default()
+        };
+        let bars = load_bars_from_csv(&path, &config).unwrap();
+        let expected = &crate::loader::load_bars_from_file("raw_data/sample.json").unwrap()[0];
+        assert_eq!(bars[0].ts_event, expected.ts_event);
+        assert_eq!((bars[0].open, bars[0].high, bars[0].close), (expected.open, expected.high, expected.close));
+        assert_eq!(bars[0].instrument_name, "CLF3");
+
+        // Too large for nanoseconds, as an integer and as a float
+        std::fs::write(&path, "ts,open,high,low,close,volume\n1668126900000,1,1,1,1,1\n9999999999999999,1,1,1,1,1\n").unwrap();
+        let err = load_bars_from_csv(&path, &config).unwrap_err().to_string();
+        assert!(err.contains("row 2"), "{err}");
+        assert!(parse_timestamp("1e30", TimestampFormat::EpochSeconds).is_err());
+        assert!(parse_timestamp("NaN", TimestampFormat::EpochSeconds).is_err());
+        assert_eq!(parse_timestamp("1.5", TimestampFormat::EpochSeconds).unwrap(), 1_500_000_000);
+    }
+
+    #[test]
+    fn test_iso_timestamps() {
+        let et = TimestampFormat::Iso8601 { naive_tz: chrono_tz::America::New_York };
+        let utc = parse_timestamp("2022-11-11T00:35:00Z", et).unwrap();
+        assert_eq!(utc, 1668126900000000000);
+        assert_eq!(parse_timestamp("2022-11-10 19:35:00-05:00", et).unwrap(), utc);
+        assert_eq!(parse_timestamp("2022-11-10 19:35:00", et).unwrap(), utc);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("csv.json");
+        std::fs::write(&path, r#"{ "columns": { "ts": "time" }, "timestamp_format": "iso:America/New_York", "delimiter": ";" }"#).unwrap();
+        let config = load_csv_config(&path).unwrap();
+        assert_eq!((config.columns.ts.as_str(), config.columns.open.as_str(), config.delimiter), ("time", "open", b';'));
+        assert_eq!(config.timestamp_format, et);
+        assert!("iso:Mars/Olympus".parse::<TimestampFormat>().is_err());
+    }
+}
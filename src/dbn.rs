@@ -0,0 +1,295 @@
+// Read Databento DBN files (OHLCV schemas) into `Bar`s
+//
+// Layout: "DBN" + version byte, u32 metadata length, metadata (dataset, schema,
+// time range, symbology mappings), then fixed-size little-endian records each
+// starting with a header whose first byte is the record length in 4-byte words.
+// Prices are 1e-9 fixed point and timestamps are UNIX nanoseconds, exactly as in
+// the JSON export, so the bars feed `resampler` unchanged.
+
+use anyhow::{bail, ensure, Context, Result};
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::Path;
+
+use crate::loader::{open_raw, Bar};
+
+const MAGIC: &[u8; 3] = b"DBN";
+const V1_SYMBOL_CSTR_LEN: usize = 22;
+const HEADER_LEN: usize = 16;
+const OHLCV_LEN: usize = HEADER_LEN + 40;
+
+/// Record types of the OHLCV schemas (1s, 1m, 1h, 1d, end-of-day)
+const OHLCV_RTYPES: [u8; 5] = [0x20, 0x21, 0x22, 0x23, 0x24];
+
+/// Header metadata of a DBN file
+#[derive(Debug, Clone)]
+pub struct DbnMetadata {
+    pub version: u8,
+    pub dataset: String,
+    pub schema: u16,
+    pub start: u64, // UNIX nanoseconds
+    pub end: u64,
+    pub symbols: Vec<String>,
+    pub symbol_map: HashMap<u32, String>, // instrument_id -> raw symbol
+}
+
+/// Little-endian cursor over a byte slice
+struct Cursor<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        ensure!(self.pos + n <= self.buf.len(), "DBN metadata truncated");
+        let out = &self.buf[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(out)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+
+    fn cstr(&mut self, len: usize) -> Result<String> {
+        let raw = self.take(len)?;
+        let end = raw.iter().position(|&b| b == 0).unwrap_or(len);
+        Ok(String::from_utf8_lossy(&raw[..end]).into_owned())
+    }
+
+    fn cstr_list(&mut self, len: usize) -> Result<Vec<String>> {
+        let count = self.u32()?;
+        (0..count).map(|_| self.cstr(len)).collect()
+    }
+}
+
+fn parse_metadata(version: u8, buf: &[u8]) -> Result<DbnMetadata> {
+    let mut c = Cursor { buf, pos: 0 };
+    let dataset = c.cstr(16)?;
+    let schema = c.u16()?;
+    let start = c.u64()?;
+    let end = c.u64()?;
+    let _limit = c.u64()?;
+    if version == 1 {
+        let _record_count = c.u64()?;
+    }
+    let _stype_in = c.u8()?;
+    let _stype_out = c.u8()?;
+    let _ts_out = c.u8()?;
+    let symbol_len = if version == 1 {
+        c.take(47)?;
+        V1_SYMBOL_CSTR_LEN
+    } else {
+        let len = c.u16()? as usize;
+        c.take(53)?;
+        len
+    };
+    let schema_definition_len = c.u32()?;
+    c.take(schema_definition_len as usize)?;
+
+    let symbols = c.cstr_list(symbol_len)?;
+    let _partial = c.cstr_list(symbol_len)?;
+    let _not_found = c.cstr_list(symbol_len)?;
+
+    // Mappings: raw symbol -> intervals whose symbol is the instrument id
+    let mut symbol_map = HashMap::new();
+    for _ in 0..c.u32()? {
+        let raw_symbol = c.cstr(symbol_len)?;
+        for _ in 0..c.u32()? {
+            let _start_date = c.u32()?;
+            let _end_date = c.u32()?;
+            let mapped = c.cstr(symbol_len)?;
+            if let Ok(id) = mapped.parse::<u32>() {
+                symbol_map.insert(id, raw_symbol.clone());
+            }
+        }
+    }
+
+    Ok(DbnMetadata { version, dataset, schema, start, end, symbols, symbol_map })
+}
+
+/// Read the metadata header, leaving the reader positioned at the first record
+pub fn read_metadata<R: Read>(reader: &mut R) -> Result<DbnMetadata> {
+    let mut prelude = [0u8; 8];
+    reader.read_exact(&mut prelude).context("DBN header truncated")?;
+    ensure!(&prelude[..3] == MAGIC, "Not a DBN stream (bad magic)");
+    let version = prelude[3];
+    ensure!((1..=3).contains(&version), "Unsupported DBN version {version}");
+    let len = u32::from_le_bytes(prelude[4..8].try_into()?) as usize;
+    let mut buf = vec![0u8; len];
+    reader.read_exact(&mut buf).context("DBN metadata truncated")?;
+    parse_metadata(version, &buf)
+}
+
+/// OHLCV bars decoded one record at a time from a DBN stream; other record types are skipped
+pub struct DbnRecords<R: Read> {
+    reader: R,
+    meta: DbnMetadata,
+    record: [u8; 255 * 4],
+    done: bool,
+}
+
+impl<R: Read> DbnRecords<R> {
+    /// Read the metadata header; records are decoded as the iterator advances
+    pub fn new(mut reader: R) -> Result<Self> {
+        let meta = read_metadata(&mut reader)?;
+        Ok(Self { reader, meta, record: [0u8; 255 * 4], done: false })
+    }
+
+    pub fn metadata(&self) -> &DbnMetadata {
+        &self.meta
+    }
+
+    /// Next OHLCV bar, or `None` at the end of the stream
+    fn next_bar(&mut self) -> Result<Option<Bar>> {
+        let record = &mut self.record;
+        loop {
+            match self.reader.read_exact(&mut record[..1]) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e.into()),
+            }
+            let len = record[0] as usize * 4;
+            if len < HEADER_LEN {
+                bail!("Invalid DBN record length {len}");
+            }
+            self.reader.read_exact(&mut record[1..len]).context("DBN record truncated")?;
+            if OHLCV_RTYPES.contains(&record[1]) {
+                ensure!(len >= OHLCV_LEN, "OHLCV record too short ({len} bytes)");
+                break;
+            }
+        }
+        let field = |i: usize| -> [u8; 8] { record[i..i + 8].try_into().unwrap() };
+        let instrument_id = u32::from_le_bytes(record[4..8].try_into()?);
+        Ok(Some(Bar {
+            instrument_name: self
+                .meta
+                .symbol_map
+                .get(&instrument_id)
+                .cloned()
+                .unwrap_or_else(|| instrument_id.to_string()),
+            instrument_id,
+            ts_event: u64::from_le_bytes(field(8)) as i64,
+            open: i64::from_le_bytes(field(16)),
+            high: i64::from_le_bytes(field(24)),
+            low: i64::from_le_bytes(field(32)),
+            close: i64::from_le_bytes(field(40)),
+            volume: u64::from_le_bytes(field(48)),
+        }))
+    }
+}
+
+impl<R: Read> Iterator for DbnRecords<R> {
+    type Item = Result<Bar>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let next = self.next_bar().transpose();
+        self.done = !matches!(next, Some(Ok(_))); // stop after the end or the first error
+        next
+    }
+}
+
+/// Read all OHLCV records from a DBN stream; other record types are skipped
+pub fn read_dbn<R: Read>(reader: R) -> Result<(DbnMetadata, Vec<Bar>)> {
+    let records = DbnRecords::new(reader)?;
+    let meta = records.metadata().clone();
+    let bars = records.collect::<Result<_>>()?;
+    Ok((meta, bars))
+}
+
+/// Load bars from a `.dbn` (or zstd-compressed `.dbn.zst`) file
+pub fn load_bars_from_dbn<P: AsRef<Path>>(path: P) -> Result<Vec<Bar>> {
+    let reader = open_raw(&path)?;
+    let (_, bars) = read_dbn(reader)
+        .with_context(|| format!("Failed to read DBN file: {}", path.as_ref().display()))?;
+    Ok(bars)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cstr(s: &str, len: usize) -> Vec<u8> {
+        let mut out = s.as_bytes().to_vec();
+        out.resize(len, 0);
+        out
+    }
+
+    fn ohlcv(rtype: u8, id: u32, ts: u64, px: i64, volume: u64) -> Vec<u8> {
+        let mut r = vec![(OHLCV_LEN / 4) as u8, rtype, 1, 0];
+        r.extend(id.to_le_bytes());
+        r.extend(ts.to_le_bytes());
+        for p in [px, px + 10, px - 10, px] {
+            r.extend(p.to_le_bytes());
+        }
+        r.extend(volume.to_le_bytes());
+        r
+    }
+
+    fn sample_v2() -> Vec<u8> {
+        let sym = 71;
+        let mut meta = cstr("GLBX.MDP3", 16);
+        meta.extend(33u16.to_le_bytes()); // ohlcv-1m
+        meta.extend(0u64.to_le_bytes());
+        meta.extend(u64::MAX.to_le_bytes());
+        meta.extend(0u64.to_le_bytes());
+        meta.extend([1, 0, 0]); // stype_in raw_symbol, stype_out instrument_id, ts_out
+        meta.extend((sym as u16).to_le_bytes());
+        meta.extend([0u8; 53]);
+        meta.extend(0u32.to_le_bytes()); // schema definition
+        meta.extend(1u32.to_le_bytes());
+        meta.extend(cstr("CLF3", sym));
+        meta.extend(0u32.to_le_bytes()); // partial
+        meta.extend(0u32.to_le_bytes()); // not found
+        meta.extend(1u32.to_le_bytes()); // mappings
+        meta.extend(cstr("CLF3", sym));
+        meta.extend(1u32.to_le_bytes());
+        meta.extend(20221111u32.to_le_bytes());
+        meta.extend(20221220u32.to_le_bytes());
+        meta.extend(cstr("93419", sym));
+
+        let mut out = b"DBN\x02".to_vec();
+        out.extend((meta.len() as u32).to_le_bytes());
+        out.extend(meta);
+        out.extend(ohlcv(0x21, 93419, 1668126900000000000, 85590000000, 3));
+        out.extend([4, 0x17, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]); // non-OHLCV record, skipped
+        out.extend(ohlcv(0x21, 93419, 1668126960000000000, 85610000000, 2));
+        out
+    }
+
+    #[test]
+    fn test_read_dbn() {
+        let (meta, bars) = read_dbn(sample_v2().as_slice()).unwrap();
+        assert_eq!(meta.dataset, "GLBX.MDP3");
+        assert_eq!(meta.symbols, vec!["CLF3".to_string()]);
+        assert_eq!(bars.len(), 2);
+        assert_eq!(bars[0].instrument_name, "CLF3");
+        assert_eq!((bars[0].ts_event, bars[0].high, bars[0].volume), (1668126900000000000, 85590000010, 3));
+        assert_eq!(bars[1].close, 85610000000);
+    }
+
+    #[test]
+    fn test_load_compressed_dbn() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sample.dbn.zst");
+        std::fs::write(&path, zstd::encode_all(sample_v2().as_slice(), 0).unwrap()).unwrap();
+        assert_eq!(crate::loader::load_bars_from_file(&path).unwrap().len(), 2);
+        let batches = crate::loader::stream_bars_from_file(&path, 1).unwrap().collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(batches.iter().map(Vec::len).collect::<Vec<_>>(), vec![1, 1]);
+    }
+}
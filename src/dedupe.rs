@@ -0,0 +1,230 @@
+// Remove repeated bars from overlapping or re-downloaded raw files
+//
+// Bars are keyed on `(instrument_id, ts_event)`. Repeats with identical values
+// are dropped silently; repeats whose prices or volume differ are conflicts,
+// resolved by the selected policy and listed in a report together with the
+// file each version came from.
+
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::{create_dir_all, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::loader::{self, Bar};
+
+/// Which version of a conflicting bar to keep
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DedupePolicy {
+    #[default]
+    First,
+    Last,
+    MaxVolume, // highest volume; ties keep the earlier bar
+    Error,     // fail on the first conflict
+}
+
+impl FromStr for DedupePolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "first" => Ok(Self::First),
+            "last" => Ok(Self::Last),
+            "max-volume" => Ok(Self::MaxVolume),
+            "error" => Ok(Self::Error),
+            other => bail!("Unknown dedupe policy '{other}' (expected first, last, max-volume or error)"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DedupeConfig {
+    pub policy: DedupePolicy,
+    pub report_dir: Option<PathBuf>, // pipeline writes `<stem>.dedupe.json` here
+    pub overlaps: OverlapIndex,      // raw files searched for bars of the same instruments
+}
+
+/// Which instruments each raw file holds, built once per run so a contract only
+/// reads the other files that share an instrument with it
+#[derive(Debug, Clone, Default)]
+pub struct OverlapIndex {
+    files: BTreeMap<PathBuf, BTreeSet<u32>>,
+}
+
+impl OverlapIndex {
+    /// Index `paths`, loading each once with `load`
+    pub fn build(paths: &[PathBuf], load: impl Fn(&Path) -> Result<Vec<Bar>>) -> Result<Self> {
+        let files = paths
+            .iter()
+            .map(|path| Ok((path.clone(), load(path)?.iter().map(|b| b.instrument_id).collect())))
+            .collect::<Result<_>>()?;
+        Ok(Self { files })
+    }
+
+    /// Other indexed files holding any instrument of `path`, in path order
+    pub fn overlaps(&self, path: &Path) -> Vec<PathBuf> {
+        let Some(ids) = self.files.get(path) else { return Vec::new() };
+        self.files
+            .iter()
+            .filter(|(other, other_ids)| other.as_path() != path && !ids.is_disjoint(other_ids))
+            .map(|(other, _)| other.clone())
+            .collect()
+    }
+}
+
+/// One version of a conflicting bar
+#[derive(Debug, Clone, Serialize)]
+pub struct Candidate {
+    pub source: String,
+    pub open: i64,
+    pub high: i64,
+    pub low: i64,
+    pub close: i64,
+    pub volume: u64,
+}
+
+impl Candidate {
+    fn new(bar: &Bar, source: &str) -> Self {
+        Self { source: source.to_string(), open: bar.open, high: bar.high, low: bar.low, close: bar.close, volume: bar.volume }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Conflict {
+    pub instrument_id: u32,
+    pub instrument_name: String,
+    pub ts_event: i64,
+    pub candidates: Vec<Candidate>, // in input order
+    pub kept: usize,                // index into `candidates`
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DedupeReport {
+    pub input: usize,
+    pub output: usize,
+    pub exact_duplicates: usize,
+    pub conflicts: Vec<Conflict>,
+}
+
+impl DedupeReport {
+    pub fn write_json<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create dedupe report: {}", path.display()))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), self)?;
+        Ok(())
+    }
+}
+
+fn same_values(a: &Bar, b: &Bar) -> bool {
+    (a.open, a.high, a.low, a.close, a.volume) == (b.open, b.high, b.low, b.close, b.volume)
+}
+
+/// Dedupe bars from several named sources, earlier sources counting as "first"
+///
+/// Output keeps the position of each key's first occurrence.
+pub fn dedupe_sources(sources: Vec<(String, Vec<Bar>)>, policy: DedupePolicy) -> Result<(Vec<Bar>, DedupeReport)> {
+    let names: Vec<String> = sources.iter().map(|(name, _)| name.clone()).collect();
+    let mut report = DedupeReport::default();
+    let mut out: Vec<Bar> = Vec::new();
+    let mut origin: Vec<usize> = Vec::new(); // source of out[i]
+    let mut index: HashMap<(u32, i64), usize> = HashMap::new();
+    let mut conflict_of: HashMap<usize, usize> = HashMap::new();
+
+    for (src, (_, bars)) in sources.into_iter().enumerate() {
+        report.input += bars.len();
+        for bar in bars {
+            let Some(&i) = index.get(&(bar.instrument_id, bar.ts_event)) else {
+                index.insert((bar.instrument_id, bar.ts_event), out.len());
+                out.push(bar);
+                origin.push(src);
+                continue;
+            };
+            if same_values(&out[i], &bar) {
+                report.exact_duplicates += 1;
+                continue;
+            }
+            if policy == DedupePolicy::Error {
+                bail!(
+                    "Conflicting bars for {} at {}: {:?} in {} vs {:?} in {}",
+                    bar.instrument_name, bar.ts_event,
+                    Candidate::new(&out[i], ""), names[origin[i]],
+                    Candidate::new(&bar, ""), names[src],
+                );
+            }
+            let c = *conflict_of.entry(i).or_insert_with(|| {
+                report.conflicts.push(Conflict {
+                    instrument_id: bar.instrument_id,
+                    instrument_name: bar.instrument_name.clone(),
+                    ts_event: bar.ts_event,
+                    candidates: vec![Candidate::new(&out[i], &names[origin[i]])],
+                    kept: 0,
+                });
+                report.conflicts.len() - 1
+            });
+            let conflict = &mut report.conflicts[c];
+            conflict.candidates.push(Candidate::new(&bar, &names[src]));
+            let replace = match policy {
+                DedupePolicy::Last => true,
+                DedupePolicy::MaxVolume => bar.volume > out[i].volume,
+                _ => false,
+            };
+            if replace {
+                conflict.kept = conflict.candidates.len() - 1;
+                out[i] = bar;
+                origin[i] = src;
+            }
+        }
+    }
+    report.output = out.len();
+    Ok((out, report))
+}
+
+/// Dedupe a single sequence of bars
+pub fn dedupe_bars(bars: Vec<Bar>, policy: DedupePolicy) -> Result<(Vec<Bar>, DedupeReport)> {
+    dedupe_sources(vec![(String::new(), bars)], policy)
+}
+
+/// Like [`loader::load_all_bars_from_folder`], but deduping across files (in path order)
+pub fn load_folder_deduped<P: AsRef<Path>>(folder: P, policy: DedupePolicy) -> Result<(Vec<Bar>, DedupeReport)> {
+    let sources = loader::list_bar_files(folder)?
+        .into_iter()
+        .map(|path| Ok((path.display().to_string(), loader::load_bars_from_file(&path)?)))
+        .collect::<Result<Vec<_>>>()?;
+    dedupe_sources(sources, policy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bar(id: u32, minute: i64, close: i64, volume: u64) -> Bar {
+        Bar { instrument_name: "CLF3".into(), instrument_id: id, ts_event: minute * 60_000_000_000, open: close, high: close, low: close, close, volume }
+    }
+
+    #[test]
+    fn test_policies_and_report() {
+        let sources = || vec![
+            ("a.json".to_string(), vec![bar(1, 0, 100, 5), bar(1, 1, 101, 5), bar(2, 1, 200, 1)]),
+            ("b.json".to_string(), vec![bar(1, 1, 101, 5), bar(1, 0, 99, 9), bar(1, 2, 102, 1)]),
+        ];
+        let (first, report) = dedupe_sources(sources(), DedupePolicy::First).unwrap();
+        assert_eq!((report.input, report.output, report.exact_duplicates), (6, 4, 1));
+        assert_eq!(report.conflicts.len(), 1);
+        let conflict = &report.conflicts[0];
+        assert_eq!((conflict.candidates[0].source.as_str(), conflict.candidates[1].source.as_str(), conflict.kept), ("a.json", "b.json", 0));
+        assert_eq!(first[0].close, 100);
+
+        let (last, report) = dedupe_sources(sources(), DedupePolicy::Last).unwrap();
+        assert_eq!((last[0].close, report.conflicts[0].kept), (99, 1));
+        let (max, _) = dedupe_sources(sources(), DedupePolicy::MaxVolume).unwrap();
+        assert_eq!(max[0].volume, 9);
+        assert!(dedupe_sources(sources(), DedupePolicy::Error).is_err());
+        assert!(dedupe_bars(vec![bar(1, 0, 1, 1), bar(1, 0, 1, 1)], DedupePolicy::Error).is_ok());
+    }
+}
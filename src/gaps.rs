@@ -0,0 +1,173 @@
+// Missing-minute analysis against the CME Globex trading schedule
+//
+// Globex energy trades Sunday 18:00 ET to Friday 17:00 ET with a daily
+// 17:00-18:00 ET maintenance break; a session is labelled with its trade date
+// (the calendar day it closes on) and is closed entirely on exchange holidays.
+// Minutes that should be open but have no bar are grouped into gaps. Short
+// gaps are normal for a 1-minute OHLCV feed (no trades printed that minute);
+// long ones, with a tighter limit during the liquid day session, are reported
+// as data outages.
+
+use anyhow::{Context, Result};
+use chrono::{Datelike, Duration, NaiveDate, TimeZone, Timelike, Utc, Weekday};
+use chrono_tz::America::New_York;
+use polars::prelude::*;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::fs::{create_dir_all, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use crate::indicators::day_session_id;
+use crate::timestamps::timestamp_millis;
+
+const MINUTE_MS: i64 = 60_000;
+
+#[derive(Debug, Clone)]
+pub struct GapConfig {
+    pub holidays: Vec<NaiveDate>,    // trade dates with no session
+    pub day_outage_minutes: i64,     // gaps this long inside 08:00-17:00 ET are outages
+    pub night_outage_minutes: i64,   // gaps this long elsewhere are outages
+    pub report_dir: Option<PathBuf>, // pipeline writes `<stem>.gaps.json` here
+}
+
+impl Default for GapConfig {
+    fn default() -> Self {
+        Self { holidays: Vec::new(), day_outage_minutes: 5, night_outage_minutes: 30, report_dir: None }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GapKind {
+    NoTrade, // short enough to be minutes without prints
+    Outage,  // too long to be quiet trading
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Gap {
+    pub start: i64,   // first missing minute, UTC epoch millis
+    pub end: i64,     // exclusive
+    pub start_et: String,
+    pub minutes: i64, // open minutes missing; the maintenance break is not counted
+    pub kind: GapKind,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct GapReport {
+    pub contract: String,
+    pub expected_minutes: i64,   // open minutes between the first and last bar
+    pub present_minutes: i64,
+    pub no_trade_minutes: i64,
+    pub outage_minutes: i64,
+    pub unexpected_minutes: i64, // bars while the market was closed
+    pub gaps: Vec<Gap>,
+}
+
+impl GapReport {
+    pub fn write_json<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create gap report: {}", path.display()))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), self)?;
+        Ok(())
+    }
+}
+
+/// Whether Globex is open during the minute starting at `ts` (UTC epoch millis)
+pub fn is_open_minute(ts: i64, holidays: &[NaiveDate]) -> bool {
+    let et = Utc.timestamp_millis_opt(ts).unwrap().with_timezone(&New_York);
+    let trade_date = match et.hour() {
+        17 => return false, // maintenance break
+        18.. => et.date_naive() + Duration::days(1),
+        _ => et.date_naive(),
+    };
+    !matches!(trade_date.weekday(), Weekday::Sat | Weekday::Sun) && !holidays.contains(&trade_date)
+}
+
+fn close_gap(report: &mut GapReport, start: i64, end: i64, minutes: i64, config: &GapConfig) {
+    let limit = if day_session_id(start).is_some() { config.day_outage_minutes } else { config.night_outage_minutes };
+    let kind = if minutes >= limit { GapKind::Outage } else { GapKind::NoTrade };
+    match kind {
+        GapKind::NoTrade => report.no_trade_minutes += minutes,
+        GapKind::Outage => report.outage_minutes += minutes,
+    }
+    let start_et = Utc.timestamp_millis_opt(start).unwrap().with_timezone(&New_York);
+    report.gaps.push(Gap { start, end, start_et: start_et.format("%Y-%m-%d %H:%M").to_string(), minutes, kind });
+}
+
+/// Compare one contract's 1-minute bars with the Globex schedule
+///
+/// Only the span between the first and last bar is checked. A gap runs across the
+/// maintenance break and weekends when no bar arrives on either side of them.
+pub fn analyze_gaps(df: &DataFrame, contract: &str, config: &GapConfig) -> Result<GapReport> {
+    let present: BTreeSet<i64> = timestamp_millis(df)?
+        .into_iter()
+        .flatten()
+        .map(|t| t - t.rem_euclid(MINUTE_MS))
+        .collect();
+    let mut report = GapReport { contract: contract.to_string(), ..Default::default() };
+    let (Some(&first), Some(&last)) = (present.first(), present.last()) else { return Ok(report) };
+
+    let mut open_gap: Option<(i64, i64)> = None; // (first missing minute, open minutes missing)
+    let mut minute = first;
+    while minute <= last {
+        let has_bar = present.contains(&minute);
+        if !is_open_minute(minute, &config.holidays) {
+            report.unexpected_minutes += has_bar as i64;
+        } else {
+            report.expected_minutes += 1;
+            if has_bar {
+                report.present_minutes += 1;
+                if let Some((start, minutes)) = open_gap.take() {
+                    close_gap(&mut report, start, minute, minutes, config);
+                }
+            } else {
+                let gap = open_gap.get_or_insert((minute, 0));
+                gap.1 += 1;
+            }
+        }
+        minute += MINUTE_MS;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn et(d: u32, h: u32, m: u32) -> i64 {
+        New_York.with_ymd_and_hms(2022, 11, d, h, m, 0).unwrap().timestamp_millis()
+    }
+
+    #[test]
+    fn test_schedule() {
+        let thanksgiving = [NaiveDate::from_ymd_opt(2022, 11, 24).unwrap()];
+        assert!(is_open_minute(et(13, 18, 0), &[]));       // Sunday open
+        assert!(!is_open_minute(et(13, 17, 59), &[]));
+        assert!(!is_open_minute(et(14, 17, 30), &[]));     // maintenance break
+        assert!(!is_open_minute(et(18, 17, 0), &[]));      // Friday close
+        assert!(!is_open_minute(et(19, 12, 0), &[]));      // Saturday
+        assert!(is_open_minute(et(23, 12, 0), &thanksgiving));
+        assert!(!is_open_minute(et(23, 18, 0), &thanksgiving));
+        assert!(!is_open_minute(et(24, 12, 0), &thanksgiving));
+    }
+
+    #[test]
+    fn test_gaps_classified() {
+        // Monday: 16:50-16:59 with 16:53 missing, break, 18:00-18:59 with a 40 minute hole
+        let mut ts: Vec<i64> = (50..60).filter(|m| *m != 53).map(|m| et(14, 16, m)).collect();
+        ts.extend((0..60).filter(|m| !(10..50).contains(m)).map(|m| et(14, 18, m)));
+        ts.push(et(14, 17, 30)); // print during the break
+        let df = df!("timestamp" => ts).unwrap();
+        let report = analyze_gaps(&df, "CLF3", &GapConfig::default()).unwrap();
+        assert_eq!(report.gaps.len(), 2);
+        assert_eq!((report.gaps[0].minutes, report.gaps[0].kind), (1, GapKind::NoTrade));
+        assert_eq!((report.gaps[1].minutes, report.gaps[1].kind), (40, GapKind::Outage));
+        assert_eq!(report.gaps[1].start_et, "2022-11-14 18:10");
+        assert_eq!((report.expected_minutes, report.present_minutes, report.unexpected_minutes), (70, 29, 1));
+    }
+}
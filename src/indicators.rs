@@ -6,7 +6,7 @@ use rayon::join;
 
 // === Helper functions ====================================================
 
-fn week_start(ts: i64) -> i64 {
+pub(crate) fn week_start(ts: i64) -> i64 {
     let dt_utc = Utc.timestamp_millis_opt(ts).unwrap();
     let dt_et = dt_utc.with_timezone(&New_York);
     let mut start_date = dt_et.date_naive()
@@ -21,7 +21,7 @@ fn week_start(ts: i64) -> i64 {
         .timestamp_millis()
 }
 
-fn session_start(ts: i64) -> i64 {
+pub(crate) fn session_start(ts: i64) -> i64 {
     let dt_utc = Utc.timestamp_millis_opt(ts).unwrap();
     let dt_et = dt_utc.with_timezone(&New_York);
     let date = dt_et.date_naive();
@@ -33,7 +33,8 @@ fn session_start(ts: i64) -> i64 {
         .timestamp_millis()
 }
 
-fn night_session_id(ts: i64) -> Option<i64> {
+#[allow(clippy::manual_range_contains)]
+pub(crate) fn night_session_id(ts: i64) -> Option<i64> {
     let dt_et = Utc.timestamp_millis_opt(ts).unwrap().with_timezone(&New_York);
     let hour = dt_et.hour();
     if hour >= 18 || hour < 8 {
@@ -44,7 +45,7 @@ fn night_session_id(ts: i64) -> Option<i64> {
     }
 }
 
-fn day_session_id(ts: i64) -> Option<i64> {
+pub(crate) fn day_session_id(ts: i64) -> Option<i64> {
     let dt_et = Utc.timestamp_millis_opt(ts).unwrap().with_timezone(&New_York);
     let hour = dt_et.hour();
     if (8..17).contains(&hour) {
@@ -198,10 +199,7 @@ fn calc_vwap_variants(ts: &[i64], high: &[f64], low: &[f64], close: &[f64], volu
 
 /// Run all indicators and append to the DataFrame
 pub fn enrich_indicators(df: &mut DataFrame) -> Result<()> {
-    let ts: Vec<i64> = df
-        .column("timestamp")?
-        .cast(&DataType::Int64)?
-        .i64()? 
+    let ts: Vec<i64> = crate::timestamps::timestamp_millis(df)?
         .into_no_null_iter()
         .collect();
     let high: Vec<f64> = df.column("high")?.f64()?.into_no_null_iter().collect();
@@ -0,0 +1,195 @@
+// Contract specifications and tick-grid normalization
+//
+// Prices divided down from 1e-9 fixed point carry float noise (85.59 may
+// come out a few ulps off after arithmetic), which breaks equality checks.
+// `normalize_ticks` snaps OHLC back onto the tick grid of each instrument and
+// flags bars whose raw integer prices were not tick multiples to begin with.
+
+use anyhow::{ensure, Context, Result};
+use polars::prelude::*;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use crate::prices::{FIXED_POINT_SCALE, PRICE_COLUMNS};
+use crate::resampler::INSTRUMENT_NAME_COL;
+
+/// Set on bars where any raw OHLC price was not a multiple of the tick size
+pub const OFF_TICK_COL: &str = "off_tick";
+
+/// Contract specification of one product
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct InstrumentSpec {
+    pub tick_size: f64,   // minimum price increment, in price units
+    pub point_value: f64, // currency per 1.0 of price
+    pub currency: String,
+}
+
+impl InstrumentSpec {
+    /// Tick size in raw 1e-9 fixed-point units
+    pub fn tick_raw(&self) -> i64 {
+        (self.tick_size * FIXED_POINT_SCALE as f64).round() as i64
+    }
+
+    /// Currency value of one tick
+    pub fn tick_value(&self) -> f64 {
+        self.tick_size * self.point_value
+    }
+}
+
+/// Specs keyed by product root (`CL`, `ES`, ...)
+#[derive(Debug, Clone, Default)]
+pub struct SpecTable {
+    pub specs: BTreeMap<String, InstrumentSpec>,
+}
+
+/// Product root of a contract symbol: `CLF3` -> `CL`, `MCLZ24` -> `MCL`
+pub fn product_root(symbol: &str) -> &str {
+    let trimmed = symbol.trim_end_matches(|c: char| c.is_ascii_digit());
+    match trimmed.char_indices().last() {
+        Some((i, c)) if i > 0 && trimmed.len() < symbol.len() && "FGHJKMNQUVXZ".contains(c) => &trimmed[..i],
+        _ => symbol,
+    }
+}
+
+impl SpecTable {
+    /// Common CME Globex energy, metal and equity index products
+    pub fn cme_defaults() -> Self {
+        let spec = |tick_size, point_value| InstrumentSpec { tick_size, point_value, currency: "USD".to_string() };
+        let specs = [
+            ("CL", spec(0.01, 1_000.0)),
+            ("MCL", spec(0.01, 100.0)),
+            ("NG", spec(0.001, 10_000.0)),
+            ("HO", spec(0.0001, 42_000.0)),
+            ("RB", spec(0.0001, 42_000.0)),
+            ("GC", spec(0.1, 100.0)),
+            ("SI", spec(0.005, 5_000.0)),
+            ("ES", spec(0.25, 50.0)),
+            ("NQ", spec(0.25, 20.0)),
+        ];
+        Self { specs: specs.into_iter().map(|(root, s)| (root.to_string(), s)).collect() }
+    }
+
+    /// Spec for a contract symbol, matched by product root
+    pub fn get(&self, symbol: &str) -> Option<&InstrumentSpec> {
+        self.specs.get(product_root(symbol)).or_else(|| self.specs.get(symbol))
+    }
+}
+
+/// Load a spec table from JSON: `{ "CL": { "tick_size": 0.01, "point_value": 1000, "currency": "USD" }, ... }`
+pub fn load_spec_table<P: AsRef<Path>>(path: P) -> Result<SpecTable> {
+    let file = File::open(&path)
+        .with_context(|| format!("Failed to open instrument specs: {}", path.as_ref().display()))?;
+    let specs: BTreeMap<String, InstrumentSpec> = serde_json::from_reader(BufReader::new(file))
+        .with_context(|| format!("Failed to parse instrument specs: {}", path.as_ref().display()))?;
+    for (root, spec) in &specs {
+        ensure!(spec.tick_raw() > 0, "Tick size of {root} must be at least 1e-9, got {}", spec.tick_size);
+    }
+    Ok(SpecTable { specs })
+}
+
+/// Tick size, in raw units, of each row's instrument
+fn tick_per_row(df: &DataFrame, table: &SpecTable) -> Result<Vec<i64>> {
+    let names = df.column(INSTRUMENT_NAME_COL)?.str()?;
+    let mut ticks: HashMap<&str, i64> = HashMap::new();
+    names
+        .iter()
+        .map(|name| {
+            let name = name.unwrap_or_default();
+            if let Some(tick) = ticks.get(name) {
+                return Ok(*tick);
+            }
+            let tick = table.get(name).with_context(|| format!("No instrument spec for symbol '{name}'"))?.tick_raw();
+            ticks.insert(name, tick);
+            Ok(tick)
+        })
+        .collect()
+}
+
+/// The existing `off_tick` flags of `df`, or all false
+fn current_flags(df: &DataFrame) -> Result<Vec<bool>> {
+    Ok(match df.column(OFF_TICK_COL) {
+        Ok(flags) => flags.bool()?.iter().map(|f| f.unwrap_or(false)).collect(),
+        Err(_) => vec![false; df.height()],
+    })
+}
+
+fn raw_price(v: f64) -> i64 {
+    (v * FIXED_POINT_SCALE as f64).round() as i64
+}
+
+/// Add an `off_tick` flag column without changing any price
+///
+/// Run this on the raw 1-minute bars: resampling keeps the flag (a bar is off tick if
+/// any of its minutes were), while off-grid prints that never reach a resampled OHLC
+/// would otherwise go unnoticed.
+pub fn flag_off_tick(df: &mut DataFrame, table: &SpecTable) -> Result<()> {
+    let tick_per_row = tick_per_row(df, table)?;
+    let mut off_tick = current_flags(df)?;
+    for name in PRICE_COLUMNS {
+        let prices = df.column(name)?.cast(&DataType::Float64)?;
+        for ((v, &tick), off) in prices.f64()?.iter().zip(&tick_per_row).zip(off_tick.iter_mut()) {
+            *off |= v.is_some_and(|v| raw_price(v) % tick != 0);
+        }
+    }
+    df.with_column(Column::new(OFF_TICK_COL.into(), off_tick))?;
+    Ok(())
+}
+
+/// Snap OHLC to each instrument's tick grid and add an `off_tick` flag column
+///
+/// Instruments are identified by the `instrument_name` column; every symbol must
+/// have a spec in `table`. An existing `off_tick` column is kept and OR-ed in, so
+/// flags set on the raw bars (see [`flag_off_tick`]) or before stitching survive.
+pub fn normalize_ticks(df: &mut DataFrame, table: &SpecTable) -> Result<()> {
+    let tick_per_row = tick_per_row(df, table)?;
+    let mut off_tick = current_flags(df)?;
+    for name in PRICE_COLUMNS {
+        let prices = df.column(name)?.cast(&DataType::Float64)?;
+        let snapped: Float64Chunked = prices
+            .f64()?
+            .iter()
+            .zip(&tick_per_row)
+            .zip(off_tick.iter_mut())
+            .map(|((v, &tick), off)| {
+                v.map(|v| {
+                    let raw = raw_price(v);
+                    *off |= raw % tick != 0;
+                    let snapped = (raw as f64 / tick as f64).round() as i64 * tick;
+                    snapped as f64 / FIXED_POINT_SCALE as f64
+                })
+            })
+            .collect();
+        df.with_column(snapped.with_name(name.into()))?;
+    }
+    df.with_column(Column::new(OFF_TICK_COL.into(), off_tick))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snap_and_flag() {
+        let noisy = 85.59 + 1e-12;
+        let mut df = df!(
+            "open" => [noisy, 85.595],
+            "high" => [85.6, 85.6],
+            "low" => [85.58, 85.58],
+            "close" => [85.59, 85.6],
+            INSTRUMENT_NAME_COL => ["CLF3", "CLF3"]
+        ).unwrap();
+        normalize_ticks(&mut df, &SpecTable::cme_defaults()).unwrap();
+        let open = df.column("open").unwrap().f64().unwrap();
+        assert_eq!((open.get(0), open.get(1)), (Some(85.59), Some(85.6)));
+        let flags: Vec<_> = df.column(OFF_TICK_COL).unwrap().bool().unwrap().into_no_null_iter().collect();
+        assert_eq!(flags, vec![false, true]);
+
+        assert_eq!((product_root("CLF3"), product_root("MCLZ24"), product_root("ES")), ("CL", "MCL", "ES"));
+        df.with_column(Column::new(INSTRUMENT_NAME_COL.into(), ["ZZF3", "ZZF3"])).unwrap();
+        assert!(normalize_ticks(&mut df, &SpecTable::cme_defaults()).is_err());
+    }
+}
@@ -1,6 +1,21 @@
+#![allow(non_snake_case)]
 pub mod loader;
+pub mod dbn;
+pub mod csv_loader;
 pub mod resampler;
+pub mod prices;
+pub mod instruments;
 pub mod indicators;
 pub mod stitcher;
 pub mod storage;
+pub mod timestamps;
+pub mod rollover;
+pub mod schedule;
+pub mod pipeline;
+pub mod manifest;
+pub mod splitter;
+pub mod dedupe;
+pub mod validation;
+pub mod gaps;
+#[cfg(test)]
 mod integration;
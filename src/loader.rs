@@ -1,8 +1,12 @@
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::fs::File;
-use std::io::{BufReader};
-use std::path::Path;
-use anyhow::{Result, Context};
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use anyhow::{bail, Result, Context};
+use polars::prelude::*;
+use crate::prices::FIXED_POINT_SCALE;
+use crate::resampler::{INSTRUMENT_ID_COL, INSTRUMENT_NAME_COL, NANOS_PER_MILLI};
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Bar {
@@ -16,11 +20,126 @@ pub struct Bar {
     pub volume: u64,
 }
 
-/// Load all bars from a single JSON file
-pub fn load_bars_from_file<P: AsRef<Path>>(path: P) -> Result<Vec<Bar>> {
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+
+/// Compression of a raw input file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Zstd,
+    Gzip,
+}
+
+/// Detect compression from the file's magic bytes, falling back to its extension
+pub fn detect_compression<P: AsRef<Path>>(path: P) -> Result<Compression> {
+    let mut file = File::open(&path)
+        .with_context(|| format!("Failed to open file: {}", path.as_ref().display()))?;
+    let mut magic = [0u8; 4];
+    let n = file.read(&mut magic)?;
+    if n >= 4 && magic == ZSTD_MAGIC {
+        return Ok(Compression::Zstd);
+    }
+    if n >= 2 && magic[..2] == GZIP_MAGIC {
+        return Ok(Compression::Gzip);
+    }
+    Ok(match path.as_ref().extension().and_then(|e| e.to_str()) {
+        Some("zst") => Compression::Zstd,
+        Some("gz") => Compression::Gzip,
+        _ => Compression::None,
+    })
+}
+
+/// Open a raw file for reading, decompressing `.zst` / `.gz` content transparently
+pub fn open_raw<P: AsRef<Path>>(path: P) -> Result<BufReader<Box<dyn Read + Send>>> {
+    let compression = detect_compression(&path)?;
     let file = File::open(&path)
         .with_context(|| format!("Failed to open file: {}", path.as_ref().display()))?;
-    let reader = BufReader::new(file);
+    let reader: Box<dyn Read + Send> = match compression {
+        Compression::None => Box::new(file),
+        Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(file)
+            .with_context(|| format!("Failed to start zstd decoder: {}", path.as_ref().display()))?),
+        Compression::Gzip => Box::new(flate2::read::MultiGzDecoder::new(BufReader::new(file))),
+    };
+    Ok(BufReader::new(reader))
+}
+
+const RAW_SUFFIXES: [&str; 8] = [".json", ".json.zst", ".json.gz", ".dbn", ".dbn.zst", ".csv", ".csv.zst", ".csv.gz"];
+
+/// Whether a path is a raw bar file: JSON (`.json`, `.json.zst`, `.json.gz`), DBN (`.dbn`, `.dbn.zst`)
+/// or CSV (`.csv`, `.csv.zst`, `.csv.gz`)
+pub fn is_raw_file<P: AsRef<Path>>(path: P) -> bool {
+    raw_file_stem(path).is_some()
+}
+
+/// Whether a path is a Databento DBN file, by extension
+pub fn is_dbn<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref()
+        .file_name()
+        .and_then(|n| n.to_str())
+        .is_some_and(|n| n.ends_with(".dbn") || n.ends_with(".dbn.zst"))
+}
+
+/// Whether a path is a CSV export, by extension; reading one needs a [`crate::csv_loader::CsvConfig`]
+pub fn is_csv<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref()
+        .file_name()
+        .and_then(|n| n.to_str())
+        .is_some_and(|n| [".csv", ".csv.zst", ".csv.gz"].iter().any(|s| n.ends_with(s)))
+}
+
+fn ensure_not_csv<P: AsRef<Path>>(path: P) -> Result<()> {
+    if is_csv(&path) {
+        bail!("{} is a CSV file; read it with csv_loader and a CsvConfig (--csv-config)", path.as_ref().display());
+    }
+    Ok(())
+}
+
+/// File name without its raw format suffix (see [`is_raw_file`])
+pub fn raw_file_stem<P: AsRef<Path>>(path: P) -> Option<String> {
+    let name = path.as_ref().file_name()?.to_str()?;
+    RAW_SUFFIXES
+        .iter()
+        .find_map(|suffix| name.strip_suffix(suffix))
+        .map(str::to_string)
+}
+
+/// Raw bar files in a folder, sorted by name
+///
+/// Fails when two files share a stem (`X.json` and `X.json.zst`), since both would
+/// be written to the same output.
+pub fn list_raw_files<P: AsRef<Path>>(folder: P) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(folder)? {
+        let path = entry?.path();
+        if path.is_file() && is_raw_file(&path) {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    let mut stems: HashMap<String, &PathBuf> = HashMap::new();
+    for path in &paths {
+        if let Some(other) = stems.insert(raw_file_stem(path).unwrap_or_default(), path) {
+            bail!("Raw files {} and {} have the same stem and would overwrite each other's output", other.display(), path.display());
+        }
+    }
+    Ok(paths)
+}
+
+/// Raw files in a folder that need no extra settings to read, i.e. all but CSV
+///
+/// The folder loaders skip CSV exports, which need a [`crate::csv_loader::CsvConfig`].
+pub fn list_bar_files<P: AsRef<Path>>(folder: P) -> Result<Vec<PathBuf>> {
+    Ok(list_raw_files(folder)?.into_iter().filter(|p| !is_csv(p)).collect())
+}
+
+/// Load all bars from a single JSON (or DBN) file
+pub fn load_bars_from_file<P: AsRef<Path>>(path: P) -> Result<Vec<Bar>> {
+    ensure_not_csv(&path)?;
+    if is_dbn(&path) {
+        return crate::dbn::load_bars_from_dbn(path);
+    }
+    let reader = open_raw(&path)?;
 
     let bars: Vec<Bar> = serde_json::Deserializer::from_reader(reader)
         .into_iter::<Bar>()
@@ -30,22 +149,118 @@ pub fn load_bars_from_file<P: AsRef<Path>>(path: P) -> Result<Vec<Bar>> {
     Ok(bars)
 }
 
-/// Load bars from all JSON files in a folder
+/// Load a JSON file straight into a DataFrame with the `bars_to_dataframe` schema
+///
+/// Columns are filled while parsing, so no intermediate `Vec<Bar>` is allocated.
+pub fn load_dataframe_from_file<P: AsRef<Path>>(path: P) -> Result<DataFrame> {
+    ensure_not_csv(&path)?;
+    if is_dbn(&path) {
+        return crate::resampler::bars_to_dataframe(&crate::dbn::load_bars_from_dbn(path)?);
+    }
+    let reader = open_raw(&path)?;
+
+    let (mut ts, mut open, mut high, mut low, mut close, mut volume) =
+        (Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    let (mut instrument_id, mut instrument_name) = (Vec::new(), Vec::new());
+    for record in serde_json::Deserializer::from_reader(reader).into_iter::<Bar>() {
+        let r = record
+            .with_context(|| format!("Failed to deserialize JSON in file: {}", path.as_ref().display()))?;
+        ts.push(r.ts_event / NANOS_PER_MILLI);
+        open.push(r.open as f64 / FIXED_POINT_SCALE as f64);
+        high.push(r.high as f64 / FIXED_POINT_SCALE as f64);
+        low.push(r.low as f64 / FIXED_POINT_SCALE as f64);
+        close.push(r.close as f64 / FIXED_POINT_SCALE as f64);
+        volume.push(r.volume as f64);
+        instrument_id.push(r.instrument_id);
+        instrument_name.push(r.instrument_name);
+    }
+
+    let mut df = df![
+        "timestamp" => ts,
+        "open" => open,
+        "high" => high,
+        "low" => low,
+        "close" => close,
+        "volume" => volume,
+        INSTRUMENT_ID_COL => instrument_id,
+        INSTRUMENT_NAME_COL => instrument_name
+    ]?;
+    crate::timestamps::normalize_timestamp(&mut df)?;
+
+    Ok(df)
+}
+
+/// Load bars from all raw files (JSON, plain or compressed, and DBN) in a folder
 pub fn load_all_bars_from_folder<P: AsRef<Path>>(folder: P) -> Result<Vec<Bar>> {
     let mut all_bars = Vec::new();
 
-    for entry in std::fs::read_dir(folder)? {
-        let entry = entry?;
-        let path = entry.path();
-        if path.extension().map_or(false, |ext| ext == "json") {
-            let mut bars = load_bars_from_file(&path)?;
-            all_bars.append(&mut bars);
-        }
+    for path in list_bar_files(folder)? {
+        let mut bars = load_bars_from_file(&path)?;
+        all_bars.append(&mut bars);
     }
 
     Ok(all_bars)
 }
 
+/// Iterator over batches of at most `batch_size` bars, decoded lazily from a raw file
+pub struct BarBatches {
+    bars: Box<dyn Iterator<Item = Result<Bar>> + Send>,
+    batch_size: usize,
+}
+
+impl BarBatches {
+    pub fn new(bars: Box<dyn Iterator<Item = Result<Bar>> + Send>, batch_size: usize) -> Self {
+        Self { bars, batch_size: batch_size.max(1) }
+    }
+}
+
+impl Iterator for BarBatches {
+    type Item = Result<Vec<Bar>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut batch = Vec::with_capacity(self.batch_size);
+        for item in self.bars.by_ref() {
+            match item {
+                Ok(bar) => batch.push(bar),
+                Err(e) => return Some(Err(e)),
+            }
+            if batch.len() == self.batch_size {
+                break;
+            }
+        }
+        (!batch.is_empty()).then_some(Ok(batch))
+    }
+}
+
+/// Stream bars from a single JSON or DBN file in batches, without holding the whole file in memory
+pub fn stream_bars_from_file<P: AsRef<Path>>(path: P, batch_size: usize) -> Result<BarBatches> {
+    ensure_not_csv(&path)?;
+    let reader = open_raw(&path)?;
+    let source = path.as_ref().display().to_string();
+    let bars: Box<dyn Iterator<Item = Result<Bar>> + Send> = if is_dbn(&path) {
+        let records = crate::dbn::DbnRecords::new(reader)
+            .with_context(|| format!("Failed to read DBN file: {source}"))?;
+        Box::new(records.map(move |r| r.with_context(|| format!("Failed to read DBN file: {source}"))))
+    } else {
+        Box::new(serde_json::Deserializer::from_reader(reader)
+            .into_iter::<Bar>()
+            .map(move |r| r.with_context(|| format!("Failed to deserialize JSON in file: {source}"))))
+    };
+    Ok(BarBatches::new(bars, batch_size))
+}
+
+/// Stream bars from all raw files in a folder (in file name order), one file after another
+pub fn stream_bars_from_folder<P: AsRef<Path>>(folder: P, batch_size: usize) -> Result<impl Iterator<Item = Result<Vec<Bar>>>> {
+    let paths = list_bar_files(folder)?;
+    Ok(paths.into_iter().flat_map(move |path| {
+        let batches: Box<dyn Iterator<Item = Result<Vec<Bar>>>> = match stream_bars_from_file(&path, batch_size) {
+            Ok(batches) => Box::new(batches),
+            Err(e) => Box::new(std::iter::once(Err(e))),
+        };
+        batches
+    }))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -65,7 +280,52 @@ mod tests {
         let mut file = File::create(&file_path).unwrap();
         writeln!(file, "{{\"instrument_name\":\"CLZ\",\"instrument_id\":1,\"ts_event\":0,\"open\":1,\"high\":1,\"low\":1,\"close\":1,\"volume\":1}}\n").unwrap();
         drop(file);
+        std::fs::write(dir.path().join("broker.csv"), "ts,open,high,low,close,volume\n").unwrap();
         let bars = load_all_bars_from_folder(dir.path()).unwrap();
         assert_eq!(bars.len(), 1);
+        assert_eq!(stream_bars_from_folder(dir.path(), 10).unwrap().count(), 1);
+
+        std::fs::copy(&file_path, dir.path().join("sample.json.gz")).unwrap();
+        let err = list_raw_files(dir.path()).unwrap_err().to_string();
+        assert!(err.contains("sample.json ") && err.contains("sample.json.gz"), "{err}");
+    }
+
+    #[test]
+    fn test_stream_batches() {
+        let all = load_bars_from_file("raw_data/sample.json").unwrap();
+        let batches: Vec<Vec<Bar>> = stream_bars_from_file("raw_data/sample.json", 100)
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(batches.len(), all.len().div_ceil(100));
+        assert!(batches.iter().all(|b| b.len() <= 100));
+        assert_eq!(batches.iter().map(Vec::len).sum::<usize>(), all.len());
+    }
+
+    #[test]
+    fn test_direct_dataframe_matches_bars() {
+        let bars = load_bars_from_file("raw_data/sample.json").unwrap();
+        let expected = crate::resampler::bars_to_dataframe(&bars).unwrap();
+        let df = load_dataframe_from_file("raw_data/sample.json").unwrap();
+        assert!(df.equals(&expected));
+    }
+
+    #[test]
+    fn test_load_compressed() {
+        let dir = tempdir().unwrap();
+        let line = "{\"instrument_name\":\"CLZ\",\"instrument_id\":1,\"ts_event\":0,\"open\":1,\"high\":1,\"low\":1,\"close\":1,\"volume\":1}\n";
+        std::fs::write(dir.path().join("a.json.zst"), zstd::encode_all(line.as_bytes(), 0).unwrap()).unwrap();
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(line.as_bytes()).unwrap();
+        let gz_bytes = gz.finish().unwrap();
+        std::fs::write(dir.path().join("b.json.gz"), &gz_bytes).unwrap();
+        // Misnamed file: detected by magic bytes, but not picked up by the folder scan
+        std::fs::write(dir.path().join("c.bin"), &gz_bytes).unwrap();
+
+        assert_eq!(detect_compression(dir.path().join("c.bin")).unwrap(), Compression::Gzip);
+        assert_eq!(load_bars_from_file(dir.path().join("c.bin")).unwrap().len(), 1);
+        assert_eq!(raw_file_stem("x/a.json.zst").as_deref(), Some("a"));
+        let bars = load_all_bars_from_folder(dir.path()).unwrap();
+        assert_eq!(bars.len(), 2);
     }
 }
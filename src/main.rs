@@ -1,86 +1,398 @@
-use std::fs;
+#![allow(non_snake_case)]
 use anyhow::Result;
-use std::collections::BTreeMap;
-use DataLoader::{loader, resampler, indicators, storage, stitcher::ContractWindow};
-use DataLoader::stitcher::stitch_contracts;
+use DataLoader::{loader, resampler, storage};
+use DataLoader::pipeline::{self, PipelineConfig};
+use DataLoader::csv_loader::load_csv_config;
+use DataLoader::dedupe::DedupeConfig;
+use DataLoader::gaps::GapConfig;
+use DataLoader::instruments::{load_spec_table, SpecTable};
+use DataLoader::manifest::{FileEntry, FileStatus, Manifest, MANIFEST_FILE};
+use DataLoader::prices::{self, PriceFormat};
+use DataLoader::resampler::SessionKind;
+use DataLoader::rollover::{load_rollover_calendar, RollMode, RolloverCalendar};
+use DataLoader::schedule::{self, RollRule};
+use DataLoader::splitter::SymbolFilter;
+use DataLoader::stitcher;
+use DataLoader::validation::ValidationConfig;
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
 
-type TsMs = i64;
 const RAW_DIR: &str = "raw_data";
 const PARQUET_DIR: &str = "parquet_data";
+const ROLLOVER_FILE: &str = "rollover.json";
 
-fn rollover_windows() -> BTreeMap<&'static str, (TsMs, TsMs)> {
-    BTreeMap::from([
-        ("2022-11-11_2022-12-20_CLF3_ohlcv1m", (1668466800000, 1671055140000)), // Nov 14 2022 18:00 - Dec 14, 2022 16:59
-        ("2022-12-11_2023-01-20_CLG3_ohlcv1m", (1671058800000, 1673992740000)), // Dec 14, 2022 18:00 - Jan 17 2023 16:59
-        ("2023-01-13_2023-02-21_CLH3_ohlcv1m", (1673996400000, 1676411940000)), // Jan 17 2023 18:00 - Feb 14 2023 16:59
-        ("2023-02-10_2023-03-21_CLJ3_ohlcv1m", (1676415600000, 1678827540000)), // Feb 14 2023 18:00 - Mar 14 2023 16:59
-        // Add more as needed
-    ])
+/// Manifest entries of stale raw files, taken before they are processed
+type Snapshots = BTreeMap<PathBuf, FileEntry>;
+
+struct Args {
+    config: PipelineConfig,
+    split: Option<SymbolFilter>, // split mode: one Parquet file per instrument
+    force: bool,                 // ignore the manifest and reprocess everything
+    append: bool,                // upsert new bars of grown files into their existing Parquet
+    roll: RollMode,              // where the stitched series rolls from one contract to the next
+}
+
+/// Validation settings, created on first use with reports written next to the Parquet output
+fn validation(config: &mut PipelineConfig) -> &mut ValidationConfig {
+    config.validation.get_or_insert_with(|| ValidationConfig {
+        report_dir: Some(PARQUET_DIR.into()),
+        ..Default::default()
+    })
 }
 
+/// Gap analysis settings, created on first use with reports written next to the Parquet output
+fn gaps(config: &mut PipelineConfig) -> &mut GapConfig {
+    config.gaps.get_or_insert_with(|| GapConfig {
+        report_dir: Some(PARQUET_DIR.into()),
+        ..Default::default()
+    })
+}
+
+/// Options: `--indicators=<per-contract|post-stitch>`, `--adjust=<none|difference|ratio>`,
+/// `--workers=<n>`, `--stream-batch=<n>`, `--interval=<5m>`, `--timeframes=<15m,1h,...>`,
+/// `--sessions=<globex,day,night>`, `--local-time`,
+/// `--split`, `--symbols=<CL*,...>`, `--include-spreads`, `--include-options`,
+/// `--prices=<float[:divisor]|fixed|decimal[:scale]>`, `--ticks`, `--tick-specs=<file.json>`,
+/// `--csv-config=<file.json>`,
+/// `--dedupe=<first|last|max-volume|error>`,
+/// `--validate=<report|fail|drop|repair>`, `--spike-atrs=<n>`, `--gaps`, `--fill=<leave|flat|null>`,
+/// `--holidays=<YYYY-MM-DD,...>`, `--force`, `--append`, `--roll=<calendar|volume|rules[:N]>`
+fn parse_args() -> Result<Args> {
+    let mut config = PipelineConfig::default();
+    let mut split: Option<SymbolFilter> = None;
+    let mut holidays = Vec::new();
+    let mut force = false;
+    let mut append = false;
+    let mut roll = RollMode::default();
+    let mut sessions: Vec<String> = Vec::new();
+    for arg in std::env::args().skip(1) {
+        if let Some(mode) = arg.strip_prefix("--indicators=") {
+            config.indicator_mode = mode.parse()?;
+        } else if let Some(method) = arg.strip_prefix("--adjust=") {
+            config.adjustment = method.parse()?;
+        } else if let Some(n) = arg.strip_prefix("--stream-batch=") {
+            config.stream_batch_size = Some(n.parse()?);
+        } else if let Some(n) = arg.strip_prefix("--workers=") {
+            config.workers = Some(n.parse()?);
+        } else if arg == "--local-time" {
+            config.local_time = true;
+        } else if let Some(interval) = arg.strip_prefix("--interval=") {
+            config.interval = interval.to_string();
+        } else if let Some(list) = arg.strip_prefix("--timeframes=") {
+            config.extra_intervals = list.split(',').filter(|s| !s.is_empty()).map(String::from).collect();
+        } else if let Some(list) = arg.strip_prefix("--sessions=") {
+            sessions = list.split(',').filter(|s| !s.is_empty()).map(String::from).collect();
+            for session in &sessions {
+                session.parse::<SessionKind>()?;
+            }
+        } else if let Some(format) = arg.strip_prefix("--prices=") {
+            config.price_format = format.parse()?;
+        } else if arg == "--ticks" {
+            config.tick_specs = Some(SpecTable::cme_defaults());
+        } else if let Some(path) = arg.strip_prefix("--csv-config=") {
+            config.csv = Some(load_csv_config(path)?);
+        } else if let Some(path) = arg.strip_prefix("--tick-specs=") {
+            config.tick_specs = Some(load_spec_table(path)?);
+        } else if let Some(policy) = arg.strip_prefix("--dedupe=") {
+            config.dedupe = Some(DedupeConfig { policy: policy.parse()?, report_dir: Some(PARQUET_DIR.into()), ..Default::default() });
+        } else if let Some(policy) = arg.strip_prefix("--validate=") {
+            validation(&mut config).policy = policy.parse()?;
+        } else if let Some(n) = arg.strip_prefix("--spike-atrs=") {
+            validation(&mut config).spike_atrs = n.parse()?;
+        } else if arg == "--gaps" {
+            gaps(&mut config);
+        } else if let Some(policy) = arg.strip_prefix("--fill=") {
+            config.fill.policy = policy.parse()?;
+        } else if let Some(list) = arg.strip_prefix("--holidays=") {
+            holidays = list
+                .split(',')
+                .filter(|s| !s.is_empty())
+                .map(|d| chrono::NaiveDate::parse_from_str(d, "%Y-%m-%d"))
+                .collect::<Result<_, _>>()?;
+        } else if arg == "--force" {
+            force = true;
+        } else if arg == "--append" {
+            append = true;
+        } else if let Some(mode) = arg.strip_prefix("--roll=") {
+            roll = mode.parse()?;
+        } else if arg == "--split" {
+            split.get_or_insert_with(SymbolFilter::default);
+        } else if let Some(list) = arg.strip_prefix("--symbols=") {
+            split.get_or_insert_with(SymbolFilter::default).patterns =
+                list.split(',').filter(|s| !s.is_empty()).map(String::from).collect();
+        } else if arg == "--include-spreads" {
+            split.get_or_insert_with(SymbolFilter::default).include_spreads = true;
+        } else if arg == "--include-options" {
+            split.get_or_insert_with(SymbolFilter::default).include_options = true;
+        } else {
+            anyhow::bail!("Unknown argument: {arg}");
+        }
+    }
+    config.extra_intervals.extend(sessions); // session bars are written like extra timeframes
+    if config.stream_batch_size.is_some() {
+        if split.is_some() || append {
+            anyhow::bail!("--stream-batch cannot be combined with --split or --append, which load whole files");
+        }
+        if config.dedupe.is_some() || config.validation.is_some() || config.gaps.is_some() {
+            anyhow::bail!("--stream-batch cannot be combined with --dedupe, --validate or --gaps, which need the whole file");
+        }
+        // Streamed extra timeframes are resampled from the primary bars
+        for interval in &config.extra_intervals {
+            resampler::ensure_derivable(&config.interval, interval)?;
+        }
+    }
+    if let Some(gaps) = &mut config.gaps {
+        gaps.holidays = holidays.clone();
+    }
+    config.fill.holidays = holidays;
+    if let Some(mut dedupe) = config.dedupe.take() {
+        dedupe.overlaps = pipeline::overlap_index(&loader::list_raw_files(RAW_DIR)?, &config)?; // dedupe across overlapping files
+        config.dedupe = Some(dedupe);
+    }
+    Ok(Args { config, split, force, append, roll })
+}
 
 fn main() -> Result<()> {
-    let windows = rollover_windows();
+    let Args { config, split, force, append, roll } = parse_args()?;
+    let manifest_path = format!("{}/{}", PARQUET_DIR, MANIFEST_FILE);
+    let mut manifest = if force { Manifest::default() } else { Manifest::load(&manifest_path)? };
+    if let Some(filter) = split {
+        let result = split_and_save_all_files(&config, &filter, &mut manifest);
+        manifest.save(&manifest_path)?;
+        return result;
+    }
+    let settings = config.fingerprint();
+    let stitch_settings = format!("{settings}:{roll:?}"); // a new roll mode restitches
+    let calendar_path = match roll {
+        RollMode::Rules { sessions_before_expiry } => {
+            let path = format!("{}/rollover.rules.json", PARQUET_DIR);
+            rules_calendar(sessions_before_expiry, &config.fill.holidays)?.write_json(&path)?;
+            path
+        }
+        _ => ROLLOVER_FILE.to_string(),
+    };
+    let calendar_entry = FileEntry::snapshot(Path::new(&calendar_path), &stitch_settings, &[])?;
+    let calendar = load_rollover_calendar(&calendar_path)?;
 
     // --- Toggle these two lines as needed ---
-    process_and_save_all_contracts()?;                         // Step 1
-    let stitched = stitch_from_parquet()?;          // Step 2
+    let result = process_and_save_all_contracts(&calendar, &config, &mut manifest, append);   // Step 1
+    manifest.save(&manifest_path)?;
+    let processed = result?;
+
+    // Back-adjustment cascades through every earlier contract, so any change restitches all
+    let stitched_path = format!("{}/stitched.parquet", PARQUET_DIR);
+    let calendar_status = manifest.status(Path::new(&calendar_path), &stitch_settings, &[])?;
+    if processed == 0 && calendar_status == FileStatus::Unchanged && Path::new(&stitched_path).exists() {
+        println!("✅ Nothing changed; {} is up to date", stitched_path);
+        return manifest.save(&manifest_path);
+    }
+    let stitched = stitch_from_parquet(&calendar, &config, roll)?;                   // Step 2
 
-    storage::write_parquet(&stitched, &(format!("{}/stitched.parquet", PARQUET_DIR)))?;
+    write_frame(&stitched, &stitched_path, &config)?;
+    manifest.insert(Path::new(&calendar_path), calendar_entry);
+    manifest.save(&manifest_path)?;
     println!("✅ Wrote stitched data with {} rows", stitched.height());
     Ok(())
 }
 
-/// Step 1: Read -> Process -> Save (per contract)
+/// Rollover calendar generated from the CL expiry rules for every contract file in the raw folder
+fn rules_calendar(sessions_before_expiry: u32, holidays: &[chrono::NaiveDate]) -> Result<RolloverCalendar> {
+    let mut stems: Vec<String> = loader::list_raw_files(RAW_DIR)?
+        .iter()
+        .filter_map(loader::raw_file_stem)
+        .filter(|stem| schedule::contract_month_from_stem(stem).is_some())
+        .collect();
+    stems.sort();
+    stems.dedup();
+    let stems: Vec<&str> = stems.iter().map(String::as_str).collect();
+    schedule::generate_calendar(&stems, &RollRule { sessions_before_expiry, holidays: holidays.to_vec() })
+}
+
+/// Raw files that are new or changed since the manifest was written, or with any output missing
+///
+/// Each stale file is snapshotted now, before it is processed, so bars appended to it
+/// during the run leave it stale for the next one.
+fn stale_files(
+    paths: Vec<PathBuf>,
+    manifest: &mut Manifest,
+    config: &PipelineConfig,
+    settings: &str,
+    outputs: impl Fn(&str) -> Vec<String>,
+) -> Result<Snapshots> {
+    let mut stale = Snapshots::new();
+    for path in paths {
+        let file_stem = loader::raw_file_stem(&path).unwrap();
+        let overlaps = config.overlapping_files(&path);
+        if manifest.status(&path, settings, &overlaps)? == FileStatus::Unchanged && outputs(&file_stem).iter().all(|out| Path::new(out).exists()) {
+            println!("Up to date: {}", file_stem);
+        } else {
+            let entry = FileEntry::snapshot(&path, settings, &overlaps)?;
+            stale.insert(path, entry);
+        }
+    }
+    Ok(stale)
+}
+
+/// Record every successfully processed file in the manifest, as it was snapshotted
+fn record_processed(results: &[(PathBuf, Result<usize>)], mut snapshots: Snapshots, manifest: &mut Manifest) {
+    for (path, result) in results {
+        if let (Ok(_), Some(entry)) = (result, snapshots.remove(path)) {
+            manifest.insert(path, entry);
+        }
+    }
+}
+
+/// Step 1: Read -> Process -> Save (per contract, in parallel)
+/// Only files that changed since the last run are processed; returns how many.
+/// With `append`, files that only grew under unchanged settings are upserted into their
+/// existing Parquet instead of being rebuilt.
 /// Comment out this call in `main` when you just want stitching.
-fn process_and_save_all_contracts() -> Result<()> {
-    for entry in fs::read_dir(RAW_DIR)? {
-        let entry = entry?;
-        let path = entry.path();
-
-        if path.extension().map_or(false, |ext| ext == "json") {
-            let file_stem = path.file_stem().unwrap().to_string_lossy().to_string();
-
-            if rollover_windows().contains_key(file_stem.as_str()) {
-                println!("Processing {}", file_stem);
-
-                // Load & transform
-                let bars = loader::load_bars_from_file(&path)?;
-                let df = resampler::bars_to_dataframe(&bars)?;
-                let mut df_5m = resampler::downsample_to_5min(&df)?;
-                indicators::enrich_indicators(&mut df_5m)?;
-
-                // Save individual contract parquet
-                let output_path = format!("{}/{}.parquet", PARQUET_DIR, file_stem);
-                storage::write_parquet(&df_5m, &output_path)?;
-            } else {
-                println!("Skipping file with unknown rollover window: {}", file_stem);
+fn process_and_save_all_contracts(calendar: &RolloverCalendar, config: &PipelineConfig, manifest: &mut Manifest, append: bool) -> Result<usize> {
+    let mut paths = Vec::new();
+    for path in loader::list_raw_files(RAW_DIR)? {
+        let file_stem = loader::raw_file_stem(&path).unwrap();
+
+        if calendar.contains(&file_stem) {
+            paths.push(path);
+        } else {
+            println!("Skipping file with unknown rollover window: {}", file_stem);
+        }
+    }
+    let settings = config.fingerprint();
+    let snapshots = stale_files(paths, manifest, config, &settings, |stem| {
+        contract_outputs(stem, config).into_iter().map(|(_, out)| out).collect()
+    })?;
+    let paths: Vec<PathBuf> = snapshots.keys().cloned().collect();
+    // Rewritten files may correct old bars, so only files that purely grew are appended to
+    let mut appendable: HashSet<PathBuf> = HashSet::new();
+    for path in paths.iter().filter(|_| append) {
+        let outputs = contract_outputs(&loader::raw_file_stem(path).unwrap(), config);
+        if outputs.iter().all(|(_, out)| Path::new(out).exists()) && manifest.only_grew(path, &settings, &config.overlapping_files(path))? {
+            appendable.insert(path.clone());
+        }
+    }
+
+    let results = pipeline::run_parallel(paths, config.workers, |path| {
+        let file_stem = loader::raw_file_stem(path).unwrap();
+        let outputs = contract_outputs(&file_stem, config);
+
+        // Load & transform (primary interval first, then any extra timeframes)
+        let frames = if appendable.contains(path) {
+            println!("Appending to {}", file_stem);
+            let stored = outputs
+                .iter()
+                .map(|(interval, out)| Ok((interval.clone(), read_frame(out)?)))
+                .collect::<Result<Vec<_>>>()?;
+            pipeline::append_contract_timeframes(path, &stored, config)?
+        } else {
+            println!("Processing {}", file_stem);
+            pipeline::process_contract_timeframes(path, config)?
+        };
+
+        for ((_, df), (_, output_path)) in frames.iter().zip(&outputs) {
+            write_frame(df, output_path, config)?;
+        }
+        Ok(frames[0].1.height())
+    })?;
+
+    record_processed(&results, snapshots, manifest);
+    let processed = results.len();
+    report(results)?;
+    Ok(processed)
+}
+
+/// `(interval, output path)` per timeframe of a contract; extra timeframes get an interval suffix
+fn contract_outputs(file_stem: &str, config: &PipelineConfig) -> Vec<(String, String)> {
+    let mut outputs = vec![(config.interval.clone(), format!("{}/{}.parquet", PARQUET_DIR, file_stem))];
+    outputs.extend(config.extra_intervals.iter().map(|interval| {
+        (interval.clone(), format!("{}/{}_{}.parquet", PARQUET_DIR, file_stem, interval))
+    }));
+    outputs
+}
+
+/// Write a frame, recording the price format in the Parquet metadata
+fn write_frame(df: &polars::prelude::DataFrame, path: &str, config: &PipelineConfig) -> Result<()> {
+    storage::write_parquet_with_metadata(df, path, &config.price_format.metadata())
+}
+
+/// Read a written frame back with dollar f64 prices, whatever its stored price format
+fn read_frame(path: &str) -> Result<polars::prelude::DataFrame> {
+    let df = storage::read_parquet(path)?;
+    let format = PriceFormat::from_metadata(&storage::read_parquet_metadata(path)?)?;
+    prices::to_float_prices(&df, &format)
+}
+
+/// Print per-file outcomes; fail only after every file has been attempted
+fn report(results: Vec<(PathBuf, Result<usize>)>) -> Result<()> {
+    let total = results.len();
+    let mut failed = 0;
+    for (path, result) in results {
+        match result {
+            Ok(rows) => println!("  {}: {} rows", path.display(), rows),
+            Err(e) => {
+                failed += 1;
+                eprintln!("  {}: FAILED: {:#}", path.display(), e);
             }
         }
     }
+    if failed > 0 {
+        anyhow::bail!("{failed} of {total} contract files failed to process");
+    }
     Ok(())
 }
 
-/// Step 2: Read saved Parquet files -> Stitch
-fn stitch_from_parquet() -> Result<polars::prelude::DataFrame> {
-    let windows = rollover_windows();
-    let mut contracts = Vec::with_capacity(windows.len());
-
-    // BTreeMap iterates in key order. Since your keys start with the start-date,
-    // this naturally yields chronological order without extra code.
-    for (name, (start_ts, end_ts)) in windows.iter() {
-        let path = format!("{}/{}.parquet", PARQUET_DIR, name); // plain String
-        let df = storage::read_parquet(&path)?;                 // takes &str
-
-        contracts.push(ContractWindow {
-            name: (*name).to_string(),
-            df,
-            start_ts: *start_ts,
-            end_ts: *end_ts,
-        });
+/// Split mode: every raw file -> one Parquet file per accepted instrument,
+/// written to `parquet_data/<file stem>/<symbol>_<instrument id>[_<interval>].parquet`
+fn split_and_save_all_files(config: &PipelineConfig, filter: &SymbolFilter, manifest: &mut Manifest) -> Result<()> {
+    let settings = format!("{}:{:?}", config.fingerprint(), filter);
+    let snapshots = stale_files(loader::list_raw_files(RAW_DIR)?, manifest, config, &settings, |stem| vec![format!("{}/{}", PARQUET_DIR, stem)])?;
+    let results = pipeline::run_parallel(snapshots.keys().cloned().collect(), config.workers, |path| {
+        let file_stem = loader::raw_file_stem(path).unwrap();
+        println!("Splitting {}", file_stem);
+
+        let mut rows = 0;
+        for (instrument, frames) in pipeline::process_instruments(path, config, filter)? {
+            for (i, (interval, df)) in frames.iter().enumerate() {
+                let output_path = if i == 0 {
+                    rows += df.height();
+                    format!("{}/{}/{}.parquet", PARQUET_DIR, file_stem, instrument)
+                } else {
+                    format!("{}/{}/{}_{}.parquet", PARQUET_DIR, file_stem, instrument, interval)
+                };
+                write_frame(df, &output_path, config)?;
+            }
+        }
+        Ok(rows)
+    })?;
+
+    record_processed(&results, snapshots, manifest);
+    report(results)
+}
+
+/// Step 2: Read saved Parquet files -> Stitch (-> enrich, in post-stitch mode)
+///
+/// With `--roll=volume` the calendar only picks the contracts; each roll happens at the
+/// session where the back month's volume overtakes, and the rolls are saved next to the output.
+fn stitch_from_parquet(calendar: &RolloverCalendar, config: &PipelineConfig, roll: RollMode) -> Result<polars::prelude::DataFrame> {
+    let mut contracts = Vec::with_capacity(calendar.windows.len());
+
+    // The calendar keeps its windows sorted by start time, so this yields
+    // chronological order without extra code.
+    for window in &calendar.windows {
+        let path = format!("{}/{}.parquet", PARQUET_DIR, window.name); // plain String
+        let df = read_frame(&path)?;                                   // stitch on dollar prices
+
+        contracts.push(window.to_contract_window(df));
+    }
+
+    if roll == RollMode::Volume {
+        let frames = contracts.into_iter().map(|c| (c.name, c.df)).collect();
+        let (windows, rolls) = stitcher::volume_crossover_windows(frames)?;
+        stitcher::write_roll_points(&rolls, format!("{}/stitched.rolls.json", PARQUET_DIR))?;
+        contracts = windows;
     }
 
-    stitch_contracts(&contracts)
+    pipeline::stitch_and_enrich(&contracts, config)
 }
 
 
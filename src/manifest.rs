@@ -0,0 +1,224 @@
+// Manifest of processed raw files for incremental runs
+//
+// Each raw file is recorded with its size, mtime, SHA-256 and the pipeline
+// version and settings that produced its output, plus the SHA-256 of any other
+// raw files whose bars were merged into it (`--dedupe` across overlapping files). A file is reprocessed only
+// when it is new, its content changed, or it was built by another version or
+// with other settings. Size and mtime are checked first so unchanged files are
+// not re-hashed; a touched but identical file only has its mtime refreshed.
+// Entries are snapshotted before a file is processed, so bars appended to a
+// file while it is being read are picked up by the next run.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::storage;
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+pub const PIPELINE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileEntry {
+    pub size: u64,
+    pub mtime_ns: u64, // since the UNIX epoch
+    pub sha256: String,
+    pub pipeline_version: String,
+    pub settings: String, // fingerprint of the pipeline settings used
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub overlaps: BTreeMap<String, String>, // SHA-256 of each overlapping raw file merged in
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileStatus {
+    New,
+    Changed,
+    Unchanged,
+}
+
+/// Processed raw files keyed by path
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub files: BTreeMap<String, FileEntry>,
+}
+
+fn to_hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    to_hex(&Sha256::digest(bytes))
+}
+
+/// SHA-256 of a file's content, as lowercase hex
+pub fn file_sha256<P: AsRef<Path>>(path: P) -> Result<String> {
+    let file = File::open(&path)
+        .with_context(|| format!("Failed to open file for hashing: {}", path.as_ref().display()))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut BufReader::new(file), &mut hasher)?;
+    Ok(to_hex(&hasher.finalize()))
+}
+
+/// SHA-256 of the first `len` bytes of a file
+fn prefix_sha256(path: &Path, len: u64) -> Result<String> {
+    let file = File::open(path).with_context(|| format!("Failed to open file for hashing: {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut BufReader::new(file).take(len), &mut hasher)?;
+    Ok(to_hex(&hasher.finalize()))
+}
+
+fn size_and_mtime(path: &Path) -> Result<(u64, u64)> {
+    let meta = fs::metadata(path).with_context(|| format!("Failed to stat {}", path.display()))?;
+    let mtime = meta.modified()?.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64);
+    Ok((meta.len(), mtime))
+}
+
+/// SHA-256 of each overlapping file, keyed by path
+fn overlap_hashes(overlaps: &[PathBuf]) -> Result<BTreeMap<String, String>> {
+    overlaps.iter().map(|p| Ok((key(p), file_sha256(p)?))).collect()
+}
+
+impl FileEntry {
+    /// Stat and hash a raw file and its `overlaps` as they are now, for recording once
+    /// its output is written
+    pub fn snapshot(path: &Path, settings: &str, overlaps: &[PathBuf]) -> Result<Self> {
+        let (size, mtime_ns) = size_and_mtime(path)?;
+        Ok(Self {
+            size,
+            mtime_ns,
+            sha256: file_sha256(path)?,
+            pipeline_version: PIPELINE_VERSION.to_string(),
+            settings: settings.to_string(),
+            overlaps: overlap_hashes(overlaps)?,
+        })
+    }
+
+    /// Whether the entry was built by this version with `settings` from these `overlaps`
+    fn same_inputs(&self, settings: &str, overlaps: &[PathBuf]) -> Result<bool> {
+        Ok(self.pipeline_version == PIPELINE_VERSION && self.settings == settings && self.overlaps == overlap_hashes(overlaps)?)
+    }
+}
+
+fn key(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+impl Manifest {
+    /// Load a manifest; a missing file gives an empty manifest
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        match File::open(&path) {
+            Ok(file) => serde_json::from_reader(BufReader::new(file))
+                .with_context(|| format!("Failed to parse manifest: {}", path.as_ref().display())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("Failed to open manifest: {}", path.as_ref().display())),
+        }
+    }
+
+    /// Save atomically, so an interrupted run leaves the previous manifest intact
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        let path = path.as_ref().to_string_lossy();
+        storage::write_bytes_atomic(&path, json.as_bytes()).with_context(|| format!("Failed to write manifest: {path}"))
+    }
+
+    /// Compare a raw file with its entry, refreshing the mtime of touched but identical files
+    ///
+    /// `overlaps` are the other raw files merged into its output; a change to any of
+    /// them, or to which files overlap, makes the file Changed too.
+    pub fn status(&mut self, path: &Path, settings: &str, overlaps: &[PathBuf]) -> Result<FileStatus> {
+        let Some(entry) = self.files.get_mut(&key(path)) else { return Ok(FileStatus::New) };
+        if !entry.same_inputs(settings, overlaps)? {
+            return Ok(FileStatus::Changed);
+        }
+        let (size, mtime_ns) = size_and_mtime(path)?;
+        if (size, mtime_ns) == (entry.size, entry.mtime_ns) {
+            return Ok(FileStatus::Unchanged);
+        }
+        if size == entry.size && file_sha256(path)? == entry.sha256 {
+            entry.mtime_ns = mtime_ns;
+            return Ok(FileStatus::Unchanged);
+        }
+        Ok(FileStatus::Changed)
+    }
+
+    /// Whether the file only grew since its output was built by this version with
+    /// `settings` and `overlaps`: it is larger, and the content that was processed is an
+    /// unchanged prefix
+    pub fn only_grew(&self, path: &Path, settings: &str, overlaps: &[PathBuf]) -> Result<bool> {
+        let Some(entry) = self.files.get(&key(path)) else { return Ok(false) };
+        if !entry.same_inputs(settings, overlaps)? {
+            return Ok(false);
+        }
+        let (size, _) = size_and_mtime(path)?;
+        Ok(size > entry.size && prefix_sha256(path, entry.size)? == entry.sha256)
+    }
+
+    /// Record a raw file as processed with `settings`, in its current state
+    pub fn record(&mut self, path: &Path, settings: &str) -> Result<()> {
+        self.insert(path, FileEntry::snapshot(path, settings, &[])?);
+        Ok(())
+    }
+
+    /// Record a raw file as processed in the state `entry` was snapshotted in
+    pub fn insert(&mut self, path: &Path, entry: FileEntry) {
+        self.files.insert(key(path), entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_lifecycle() {
+        let dir = tempfile::tempdir().unwrap();
+        let raw = dir.path().join("a.json");
+        fs::write(&raw, "{}").unwrap();
+        let mut manifest = Manifest::default();
+        assert_eq!(manifest.status(&raw, "s1", &[]).unwrap(), FileStatus::New);
+        manifest.record(&raw, "s1").unwrap();
+
+        let path = dir.path().join(MANIFEST_FILE);
+        manifest.save(&path).unwrap();
+        let mut manifest = Manifest::load(&path).unwrap();
+        assert_eq!(manifest.status(&raw, "s1", &[]).unwrap(), FileStatus::Unchanged);
+        assert_eq!(manifest.status(&raw, "s2", &[]).unwrap(), FileStatus::Changed);
+
+        manifest.files.get_mut(&key(&raw)).unwrap().mtime_ns = 0; // touched, same content
+        assert_eq!(manifest.status(&raw, "s1", &[]).unwrap(), FileStatus::Unchanged);
+        fs::write(&raw, "{\"x\":1}").unwrap();
+        assert_eq!(manifest.status(&raw, "s1", &[]).unwrap(), FileStatus::Changed);
+        assert!(Manifest::load(dir.path().join("missing.json")).unwrap().files.is_empty());
+
+        // A contract deduped against another file is stale once that file changes
+        let other = dir.path().join("b.json");
+        fs::write(&other, "{}").unwrap();
+        let overlaps = vec![other.clone()];
+        manifest.insert(&raw, FileEntry::snapshot(&raw, "s1", &overlaps).unwrap());
+        assert_eq!(manifest.status(&raw, "s1", &overlaps).unwrap(), FileStatus::Unchanged);
+        assert_eq!(manifest.status(&raw, "s1", &[]).unwrap(), FileStatus::Changed);
+        fs::write(&other, "{\"x\":2}").unwrap();
+        assert_eq!(manifest.status(&raw, "s1", &overlaps).unwrap(), FileStatus::Changed);
+    }
+
+    #[test]
+    fn test_snapshot_before_growth() {
+        let dir = tempfile::tempdir().unwrap();
+        let raw = dir.path().join("a.json");
+        fs::write(&raw, "{}\n").unwrap();
+        let entry = FileEntry::snapshot(&raw, "s1", &[]).unwrap();
+        fs::write(&raw, "{}\n{}\n").unwrap(); // bars appended while the file was processed
+        let mut manifest = Manifest::default();
+        manifest.insert(&raw, entry.clone());
+        assert_eq!(manifest.status(&raw, "s1", &[]).unwrap(), FileStatus::Changed);
+        assert!(manifest.only_grew(&raw, "s1", &[]).unwrap() && !manifest.only_grew(&raw, "s2", &[]).unwrap());
+
+        fs::write(&raw, "[]\n{}\n").unwrap(); // rewritten rather than grown
+        assert!(!manifest.only_grew(&raw, "s1", &[]).unwrap());
+    }
+}
@@ -0,0 +1,648 @@
+// Per-contract processing and stitching, with a choice of when indicators run
+//
+// Indicators computed per contract restart at every contract boundary and see
+// prices from days the contract wasn't front month. Post-stitch mode instead
+// runs them once over the continuous (optionally back-adjusted) series.
+
+use anyhow::{bail, ensure, Result};
+use polars::prelude::*;
+use rayon::prelude::*;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::csv_loader::{self, CsvConfig};
+use crate::dedupe::{self, DedupeConfig, OverlapIndex};
+use crate::gaps::{self, GapConfig};
+use crate::instruments::{self, SpecTable};
+use crate::prices::{self, PriceFormat};
+use crate::resampler::{FillConfig, FillPolicy};
+use crate::splitter::{self, SymbolFilter};
+use crate::stitcher::{stitch_contracts_adjusted, Adjustment, ContractWindow};
+use crate::validation::{self, ValidationConfig, ValidationReport};
+use crate::{indicators, loader, manifest, resampler, timestamps};
+
+/// Indicator columns that are price levels and so move with back-adjustment
+const PRICE_LEVEL_INDICATORS: [&str; 6] = ["vwap", "vwapn", "vwapd", "ema_9", "ema_14", "ema_21"];
+
+/// Indicator columns that are price distances: scaled by ratio adjustment only
+const PRICE_RANGE_INDICATORS: [&str; 1] = ["atr_14"];
+
+/// `(interval, frame)` pairs for one contract, primary interval first
+pub type Timeframes = Vec<(String, DataFrame)>;
+
+/// When indicators are computed relative to stitching
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IndicatorMode {
+    #[default]
+    PerContract,
+    PostStitch,
+}
+
+impl FromStr for IndicatorMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "per-contract" => Ok(Self::PerContract),
+            "post-stitch" => Ok(Self::PostStitch),
+            other => bail!("Unknown indicator mode '{other}' (expected per-contract or post-stitch)"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PipelineConfig {
+    pub indicator_mode: IndicatorMode,
+    pub adjustment: Adjustment,
+    pub stream_batch_size: Option<usize>, // stream raw files in batches of this many bars
+    pub workers: Option<usize>,           // parallel file workers; None uses all cores
+    pub interval: String,                 // resolution used for stitching
+    pub extra_intervals: Vec<String>,     // additional resolutions written per contract
+    pub local_time: bool,                 // add a `timestamp_et` America/New_York column
+    pub price_format: PriceFormat,        // representation of the output price columns
+    pub tick_specs: Option<SpecTable>,    // snap OHLC to the tick grid and flag off-tick bars
+    pub dedupe: Option<DedupeConfig>,     // drop repeated (instrument_id, ts_event) bars, also across files
+    pub validation: Option<ValidationConfig>, // check raw 1-minute bars before resampling
+    pub gaps: Option<GapConfig>,          // report missing minutes against the Globex schedule
+    pub fill: FillConfig,                 // rows for empty in-session buckets
+    pub csv: Option<CsvConfig>,           // how to read `.csv` raw files
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        Self {
+            indicator_mode: IndicatorMode::default(),
+            adjustment: Adjustment::default(),
+            stream_batch_size: None,
+            workers: None,
+            interval: "5m".to_string(),
+            extra_intervals: Vec::new(),
+            local_time: false,
+            price_format: PriceFormat::default(),
+            tick_specs: None,
+            dedupe: None,
+            validation: None,
+            gaps: None,
+            fill: FillConfig::default(),
+            csv: None,
+        }
+    }
+}
+
+impl PipelineConfig {
+    /// Short hash of every setting that affects the output (the worker count does not)
+    ///
+    /// Overlapping files are tracked per contract in the manifest instead, so adding a
+    /// raw file only invalidates the contracts it overlaps.
+    pub fn fingerprint(&self) -> String {
+        let dedupe = self.dedupe.as_ref().map(|d| DedupeConfig { overlaps: OverlapIndex::default(), ..d.clone() });
+        let settings = PipelineConfig { workers: None, dedupe, ..self.clone() };
+        manifest::sha256_hex(format!("{settings:?}").as_bytes())[..16].to_string()
+    }
+
+    /// Other raw files whose bars are merged into `path`'s output
+    pub fn overlapping_files(&self, path: &Path) -> Vec<PathBuf> {
+        self.dedupe.as_ref().map(|d| d.overlaps.overlaps(path)).unwrap_or_default()
+    }
+}
+
+/// Load -> resample -> (per-contract mode only) enrich a single raw contract file
+pub fn process_contract<P: AsRef<Path>>(path: P, config: &PipelineConfig) -> Result<DataFrame> {
+    let base = PipelineConfig { extra_intervals: Vec::new(), ..config.clone() };
+    let (_, df) = process_contract_timeframes(path, &base)?.swap_remove(0);
+    Ok(df)
+}
+
+/// Like [`process_contract`], but also produce every `extra_intervals` resolution from the same load
+///
+/// Returns `(interval, frame)` pairs, primary interval first. When streaming, the extra
+/// resolutions are built from the primary bars, so they must be multiples of it.
+pub fn process_contract_timeframes<P: AsRef<Path>>(path: P, config: &PipelineConfig) -> Result<Timeframes> {
+    match config.stream_batch_size {
+        Some(_) if config.dedupe.is_some() || config.validation.is_some() || config.gaps.is_some() => {
+            bail!("Dedupe, bar validation and gap analysis need the whole file; unset stream_batch_size")
+        }
+        Some(batch_size) => {
+            for interval in &config.extra_intervals {
+                resampler::ensure_derivable(&config.interval, interval)?;
+            }
+            let batches = match &config.csv {
+                Some(csv) if loader::is_csv(&path) => loader::BarBatches::new(Box::new(csv_loader::csv_bars(path.as_ref(), csv)?), batch_size),
+                _ => loader::stream_bars_from_file(&path, batch_size)?,
+            };
+            let primary = resampler::resample_stream_with(batches, &config.interval, |minutes| match &config.tick_specs {
+                Some(table) => instruments::flag_off_tick(minutes, table),
+                None => Ok(()),
+            })?;
+            ensure_single_instrument(&primary, path.as_ref())?;
+            let mut frames = resampler::resample_many(&primary, &config.extra_intervals.iter().map(String::as_str).collect::<Vec<_>>())?;
+            frames.insert(0, primary);
+            finish_timeframes(frames, config)
+        }
+        None => resample_and_enrich(&load_minute_frame(&path, config)?, config),
+    }
+}
+
+/// Load a raw file's 1-minute bars through dedupe, validation and gap analysis as configured
+fn load_minute_frame<P: AsRef<Path>>(path: P, config: &PipelineConfig) -> Result<DataFrame> {
+    check_minute_frame(load_raw_frame(&path, config)?, path.as_ref(), config)
+}
+
+/// All 1-minute bars of a raw file, deduped when configured
+fn load_raw_frame<P: AsRef<Path>>(path: P, config: &PipelineConfig) -> Result<DataFrame> {
+    match config.dedupe {
+        Some(_) => resampler::bars_to_dataframe(&load_deduped(&path, config)?),
+        None if loader::is_csv(&path) => resampler::bars_to_dataframe(&load_bars(&path, config)?),
+        None => loader::load_dataframe_from_file(&path),
+    }
+}
+
+/// Validate, flag off-tick prices and gap-check loaded 1-minute bars, writing the reports
+fn check_minute_frame(mut df: DataFrame, path: &Path, config: &PipelineConfig) -> Result<DataFrame> {
+    if let Some(v) = &config.validation {
+        let (valid, report) = validation::validate_dataframe(&df, v)?;
+        finish_validation(&report, path, v)?;
+        df = valid;
+    }
+    ensure_single_instrument(&df, path)?;
+    if let Some(table) = &config.tick_specs {
+        instruments::flag_off_tick(&mut df, table)?; // before resampling can hide off-grid minutes
+    }
+    let stem = loader::raw_file_stem(path).unwrap_or_default();
+    report_gaps(&df, &stem, &stem, config)?;
+    Ok(df)
+}
+
+/// Indicators, fills and stitching run over one series, so a contract file must hold one instrument
+fn ensure_single_instrument(df: &DataFrame, path: &Path) -> Result<()> {
+    let Ok(ids) = df.column(resampler::INSTRUMENT_ID_COL) else { return Ok(()) };
+    let n = ids.n_unique()?;
+    ensure!(n <= 1, "{} holds {n} instruments; split it per instrument (--split) instead", path.display());
+    Ok(())
+}
+
+/// Append mode: upsert the raw bars from each stored frame's last bucket onwards
+///
+/// `stored` holds the frames previously written for this file, with dollar f64 prices
+/// (see [`prices::to_float_prices`]), in the order [`process_contract_timeframes`]
+/// returns them. Raw bars before the last stored bucket are assumed unchanged, so only
+/// call this for files that grew at the end; a full run picks up corrections. The whole file is still read, but only bars from
+/// the start of the earliest re-finished week are validated and gap-checked, so those
+/// reports cover just that stretch.
+pub fn append_contract_timeframes<P: AsRef<Path>>(path: P, stored: &Timeframes, config: &PipelineConfig) -> Result<Timeframes> {
+    ensure!(config.stream_batch_size.is_none(), "Append mode loads the whole file; unset stream_batch_size");
+    let last_stored = stored
+        .iter()
+        .map(|(_, existing)| Ok(timestamps::timestamp_millis(existing)?.max()))
+        .collect::<Result<Option<Vec<i64>>>>()?;
+    let mut minutes = load_raw_frame(&path, config)?;
+    if let Some(since) = last_stored.and_then(|last| last.into_iter().min()) {
+        let since = since.min(indicators::week_start(since)); // a Sunday evening belongs to the next week
+        let ts = timestamps::timestamp_millis(&minutes)?;
+        minutes = minutes.filter(&ts.gt_eq(since))?;
+    }
+    let minutes = check_minute_frame(minutes, path.as_ref(), config)?;
+    let ts = timestamps::timestamp_millis(&minutes)?;
+    stored
+        .iter()
+        .map(|(interval, existing)| {
+            let from = timestamps::timestamp_millis(existing)?.max().unwrap_or(i64::MIN);
+            let fresh = resampler::resample(&minutes.filter(&ts.gt_eq(from))?, interval)?;
+            Ok((interval.clone(), upsert_bars(existing, &fresh, interval, config)?))
+        })
+        .collect()
+}
+
+/// Merge freshly resampled bars into a stored, time-sorted contract frame
+///
+/// New bars replace stored rows with the same timestamp. Indicators reset weekly, so
+/// stored rows before the week of the first new bar are kept as they are and only the
+/// rest is finished again. `existing` must hold dollar f64 prices; the result is
+/// converted to `config.price_format`.
+pub fn upsert_bars(existing: &DataFrame, new: &DataFrame, interval: &str, config: &PipelineConfig) -> Result<DataFrame> {
+    let mut new = new.clone();
+    if let Some(table) = &config.tick_specs {
+        instruments::normalize_ticks(&mut new, table)?; // gives `new` the stored off_tick column
+    }
+    let new_ts = timestamps::timestamp_millis(&new)?;
+    let Some(first_new) = new_ts.min() else {
+        return prices::apply_price_format(existing, &config.price_format);
+    };
+    let warmup = indicators::week_start(first_new);
+    let split = timestamps::timestamp_millis(existing)?
+        .into_no_null_iter()
+        .position(|t| indicators::week_start(t) >= warmup)
+        .unwrap_or(existing.height());
+
+    // Re-finish the stored tail from its bars, minus fill rows and replaced timestamps
+    let replaced: HashSet<i64> = new_ts.into_no_null_iter().collect();
+    let columns = new.get_column_names_owned();
+    let tail = existing.slice(split as i64, existing.height() - split).select(columns)?;
+    let keep: BooleanChunked = timestamps::timestamp_millis(&tail)?
+        .iter()
+        .zip(tail.column("close")?.f64()?.iter())
+        .map(|(t, close)| close.is_some() && !t.is_some_and(|t| replaced.contains(&t)))
+        .collect();
+    let mut tail = tail
+        .filter(&keep)?
+        .vstack(&new)?
+        .sort(["timestamp"], SortMultipleOptions::default())?;
+    finish_frame(&mut tail, interval, config)?;
+
+    let head = existing.slice(0, split).select(tail.get_column_names_owned())?;
+    prices::apply_price_format(&head.vstack(&tail)?, &config.price_format)
+}
+
+/// Load all bars of a raw file, reading CSV exports with `config.csv`
+fn load_bars<P: AsRef<Path>>(path: P, config: &PipelineConfig) -> Result<Vec<loader::Bar>> {
+    match &config.csv {
+        Some(csv) if loader::is_csv(&path) => csv_loader::load_bars_from_csv(path, csv),
+        _ => loader::load_bars_from_file(path),
+    }
+}
+
+/// Load bars, deduping on `(instrument_id, ts_event)` when configured
+///
+/// Bars of the same instruments in overlapping files (see [`overlap_index`]) are merged in, so repeats
+/// across overlapping downloads are resolved too. The report is saved as
+/// `<report_dir>/<stem>.dedupe.json`.
+fn load_deduped<P: AsRef<Path>>(path: P, config: &PipelineConfig) -> Result<Vec<loader::Bar>> {
+    let bars = load_bars(&path, config)?;
+    let Some(d) = &config.dedupe else { return Ok(bars) };
+    let sources = overlapping_sources(path.as_ref(), bars, &d.overlaps.overlaps(path.as_ref()), config)?;
+    let merged = sources.len() > 1;
+    let (mut bars, report) = dedupe::dedupe_sources(sources, d.policy)?;
+    if merged {
+        bars.sort_by_key(|b| b.ts_event); // interleave the files; stable, so each file keeps its order
+    }
+    if let Some(dir) = &d.report_dir {
+        let stem = loader::raw_file_stem(&path).unwrap_or_default();
+        report.write_json(dir.join(format!("{stem}.dedupe.json")))?;
+    }
+    Ok(bars)
+}
+
+/// `bars` of `path` plus the bars of its instrument ids found in `overlaps`, as named
+/// dedupe sources in path order (so `first` means the first file in the raw folder)
+fn overlapping_sources(path: &Path, bars: Vec<loader::Bar>, overlaps: &[PathBuf], config: &PipelineConfig) -> Result<Vec<(String, Vec<loader::Bar>)>> {
+    let ids: HashSet<u32> = bars.iter().map(|b| b.instrument_id).collect();
+    let mut sources = vec![(path.to_path_buf(), bars)];
+    for other in overlaps {
+        let mut bars = load_bars(other, config)?;
+        bars.retain(|b| ids.contains(&b.instrument_id));
+        if !bars.is_empty() {
+            sources.push((other.clone(), bars));
+        }
+    }
+    sources.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(sources.into_iter().map(|(p, bars)| (p.display().to_string(), bars)).collect())
+}
+
+/// Index which instruments each raw file holds, for deduping contracts across files
+///
+/// CSV files are skipped unless `config.csv` says how to read them.
+pub fn overlap_index(paths: &[PathBuf], config: &PipelineConfig) -> Result<OverlapIndex> {
+    let readable: Vec<PathBuf> = paths.iter().filter(|p| config.csv.is_some() || !loader::is_csv(p)).cloned().collect();
+    OverlapIndex::build(&readable, |path| load_bars(path, config))
+}
+
+/// Save the report as `<report_dir>/<stem>.validation.json`, then apply the fail policy
+fn finish_validation(report: &ValidationReport, path: &Path, config: &ValidationConfig) -> Result<()> {
+    if let Some(dir) = &config.report_dir {
+        let stem = loader::raw_file_stem(path).unwrap_or_default();
+        report.write_json(dir.join(format!("{stem}.validation.json")))?;
+    }
+    report.enforce(config.policy)
+}
+
+/// Gap-check 1-minute bars of `contract`, saving `<report_dir>/<file>.gaps.json`
+fn report_gaps(df: &DataFrame, contract: &str, file: &str, config: &PipelineConfig) -> Result<()> {
+    let Some(g) = &config.gaps else { return Ok(()) };
+    let report = gaps::analyze_gaps(df, contract, g)?;
+    if let Some(dir) = &g.report_dir {
+        report.write_json(dir.join(format!("{file}.gaps.json")))?;
+    }
+    Ok(())
+}
+
+/// Split a multi-instrument raw file and run the per-contract pipeline for each accepted instrument
+///
+/// Returns `(file stem, timeframes)` per instrument, in instrument id order; see
+/// [`splitter::InstrumentBars::file_stem`].
+pub fn process_instruments<P: AsRef<Path>>(path: P, config: &PipelineConfig, filter: &SymbolFilter) -> Result<Vec<(String, Timeframes)>> {
+    ensure!(config.stream_batch_size.is_none(), "Split mode loads the whole file; unset stream_batch_size");
+    let mut bars = load_deduped(&path, config)?;
+    if let Some(v) = &config.validation {
+        let (valid, report) = validation::validate_bars(bars, v);
+        finish_validation(&report, path.as_ref(), v)?;
+        bars = valid;
+    }
+    let stem = loader::raw_file_stem(&path).unwrap_or_default();
+    splitter::split_by_instrument(bars, filter)
+        .into_iter()
+        .map(|inst| {
+            let mut df = resampler::bars_to_dataframe(&inst.bars)?;
+            if let Some(table) = &config.tick_specs {
+                instruments::flag_off_tick(&mut df, table)?;
+            }
+            report_gaps(&df, &inst.symbol, &format!("{stem}/{}", inst.file_stem()), config)?;
+            Ok((inst.file_stem(), resample_and_enrich(&df, config)?))
+        })
+        .collect()
+}
+
+fn intervals(config: &PipelineConfig) -> Vec<&str> {
+    let mut intervals = vec![config.interval.as_str()];
+    intervals.extend(config.extra_intervals.iter().map(String::as_str));
+    intervals
+}
+
+/// Resample an in-memory 1-minute frame to every configured interval, then enrich
+fn resample_and_enrich(df: &DataFrame, config: &PipelineConfig) -> Result<Timeframes> {
+    let frames = resampler::resample_many(df, &intervals(config))?;
+    finish_timeframes(frames, config)
+}
+
+fn finish_timeframes(mut frames: Vec<DataFrame>, config: &PipelineConfig) -> Result<Timeframes> {
+    for (df, interval) in frames.iter_mut().zip(intervals(config)) {
+        finish_frame(df, interval, config)?;
+        *df = prices::apply_price_format(df, &config.price_format)?;
+    }
+    Ok(intervals(config).into_iter().map(String::from).zip(frames).collect())
+}
+
+/// Tick normalization, gap fill, indicators and local time for one resampled frame
+///
+/// Flat fills feed the indicators like real bars; null rows are added after them
+/// so indicator columns stay null there as well.
+fn finish_frame(df: &mut DataFrame, interval: &str, config: &PipelineConfig) -> Result<()> {
+    if let Some(table) = &config.tick_specs {
+        instruments::normalize_ticks(df, table)?;
+    }
+    if config.fill.policy == FillPolicy::Flat {
+        *df = resampler::fill_gaps(df, interval, &config.fill)?;
+    }
+    if config.indicator_mode == IndicatorMode::PerContract {
+        indicators::enrich_indicators(df)?;
+    }
+    if config.fill.policy == FillPolicy::Null {
+        *df = resampler::fill_gaps(df, interval, &config.fill)?;
+    }
+    if config.local_time {
+        timestamps::with_local_time(df)?;
+    }
+    Ok(())
+}
+
+/// Run `f` over every path on a pool of `workers` threads
+///
+/// Results come back sorted by path regardless of scheduling, and a failing file
+/// is reported in its own slot instead of aborting the rest of the batch.
+pub fn run_parallel<T, F>(mut paths: Vec<PathBuf>, workers: Option<usize>, f: F) -> Result<Vec<(PathBuf, Result<T>)>>
+where
+    T: Send,
+    F: Fn(&Path) -> Result<T> + Sync,
+{
+    paths.sort();
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(workers.unwrap_or(0))
+        .build()?;
+    Ok(pool.install(|| {
+        paths
+            .into_par_iter()
+            .map(|path| {
+                let result = f(&path);
+                (path, result)
+            })
+            .collect()
+    }))
+}
+
+/// Stitch contract windows and, in post-stitch mode, enrich the continuous series
+///
+/// Windows must hold dollar f64 prices (see [`prices::to_float_prices`]); the result is
+/// converted to `config.price_format`.
+pub fn stitch_and_enrich(windows: &[ContractWindow], config: &PipelineConfig) -> Result<DataFrame> {
+    let (levels, ranges): (&[&str], &[&str]) = match config.indicator_mode {
+        IndicatorMode::PerContract => (&PRICE_LEVEL_INDICATORS, &PRICE_RANGE_INDICATORS),
+        IndicatorMode::PostStitch => (&[], &[]),
+    };
+    let mut stitched = stitch_contracts_adjusted(windows, config.adjustment, levels, ranges)?;
+    // Difference adjustments move prices by whole ticks; ratio-adjusted prices are off-grid by design
+    if let Some(table) = config.tick_specs.as_ref().filter(|_| config.adjustment != Adjustment::Ratio && stitched.height() > 0) {
+        instruments::normalize_ticks(&mut stitched, table)?;
+    }
+    if config.indicator_mode == IndicatorMode::PostStitch && stitched.height() > 0 {
+        stitched = stitched.sort(["timestamp"], SortMultipleOptions::default())?;
+        if config.fill.policy == FillPolicy::Null {
+            stitched = stitched.lazy().filter(col("close").is_not_null()).collect()?;
+        }
+        indicators::enrich_indicators(&mut stitched)?;
+        if config.fill.policy == FillPolicy::Null {
+            stitched = resampler::fill_gaps(&stitched, &config.interval, &config.fill)?;
+        }
+    }
+    if config.local_time && stitched.height() > 0 {
+        timestamps::with_local_time(&mut stitched)?;
+    }
+    prices::apply_price_format(&stitched, &config.price_format)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Tue Nov 15 2022 00:00 UTC, mid-week so the weekly indicator reset doesn't interfere
+    const T0: i64 = 1668470400000;
+    const STEP: i64 = 300_000;
+
+    fn contract(level: f64) -> DataFrame {
+        let ts: Vec<i64> = (0..20).map(|i| T0 + i * STEP).collect();
+        df!(
+            "timestamp" => ts,
+            "open" => [level; 20],
+            "high" => [level + 0.5; 20],
+            "low" => [level - 0.5; 20],
+            "close" => [level; 20],
+            "volume" => [10.0; 20]
+        ).unwrap()
+    }
+
+    /// NDJSON raw bars of one instrument, one per minute of `minutes` after `start` (ms),
+    /// with `ohlcv(minute)` giving raw open, high, low, close and volume
+    fn minute_bars(name: &str, id: u32, start: i64, minutes: std::ops::Range<i64>, ohlcv: impl Fn(i64) -> [i64; 5]) -> String {
+        minutes
+            .map(|m| {
+                let [open, high, low, close, volume] = ohlcv(m);
+                format!(
+                    "{{\"instrument_name\":\"{name}\",\"instrument_id\":{id},\"ts_event\":{},\"open\":{open},\"high\":{high},\"low\":{low},\"close\":{close},\"volume\":{volume}}}\n",
+                    (start + m * 60_000) * 1_000_000
+                )
+            })
+            .collect()
+    }
+
+    fn flat(_: i64) -> [i64; 5] {
+        [1, 2, 1, 1, 1]
+    }
+
+    fn windows(enrich: bool) -> Vec<ContractWindow> {
+        let (mut a, mut b) = (contract(10.0), contract(20.0));
+        if enrich {
+            indicators::enrich_indicators(&mut a).unwrap();
+            indicators::enrich_indicators(&mut b).unwrap();
+        }
+        let roll = T0 + 10 * STEP;
+        vec![
+            ContractWindow { name: "A".into(), df: a, start_ts: T0, end_ts: roll },
+            ContractWindow { name: "B".into(), df: b, start_ts: roll, end_ts: i64::MAX },
+        ]
+    }
+
+    fn ema_at_roll(df: &DataFrame) -> f64 {
+        df.column("ema_9").unwrap().f64().unwrap().get(10).unwrap()
+    }
+
+    #[test]
+    fn test_indicator_mode_at_roll() {
+        let per_contract = PipelineConfig { indicator_mode: IndicatorMode::PerContract, ..Default::default() };
+        let post_stitch = PipelineConfig { indicator_mode: IndicatorMode::PostStitch, ..Default::default() };
+
+        // Per contract: B's EMA was warmed up on B's own prices before it became front month
+        let df = stitch_and_enrich(&windows(true), &per_contract).unwrap();
+        assert_eq!(ema_at_roll(&df), 20.0);
+
+        // Post stitch: the EMA carries over from A and only starts moving towards B at the roll
+        let df = stitch_and_enrich(&windows(false), &post_stitch).unwrap();
+        let ema = ema_at_roll(&df);
+        assert!(ema > 10.0 && ema < 20.0, "ema at roll was {ema}");
+    }
+
+    #[test]
+    fn test_run_parallel_reports_per_file() {
+        let paths = vec![PathBuf::from("raw_data/missing.json"), PathBuf::from("raw_data/sample.json")];
+        let results = run_parallel(paths, Some(2), |p| process_contract(p, &PipelineConfig::default())).unwrap();
+        assert_eq!(results[0].0, PathBuf::from("raw_data/missing.json"));
+        assert!(results[0].1.is_err());
+        assert!(results[1].1.as_ref().unwrap().height() > 0);
+    }
+
+    #[test]
+    fn test_process_instruments() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mixed.json");
+        let lines = [(1, "CLF3"), (2, "CLG3"), (3, "CLF3-CLG3")].map(|(id, name)| minute_bars(name, id, T0, 0..20, flat));
+        std::fs::write(&path, lines.concat()).unwrap();
+        let out = process_instruments(&path, &PipelineConfig::default(), &SymbolFilter::default()).unwrap();
+        let symbols: Vec<_> = out.iter().map(|(s, _)| s.as_str()).collect();
+        assert_eq!(symbols, vec!["CLF3_1", "CLG3_2"]);
+        let (_, df) = &out[0].1[0];
+        assert_eq!(df.height(), 4);
+        assert!(df.column("ema_9").is_ok());
+    }
+
+    #[test]
+    fn test_contract_file_with_two_instruments() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mixed.json");
+        let lines: String = (0..20i64)
+            .flat_map(|m| [(1, "CLF3"), (2, "CLG3")].map(|(id, name)| minute_bars(name, id, T0, m..m + 1, flat)))
+            .collect();
+        std::fs::write(&path, lines).unwrap();
+        let err = process_contract_timeframes(&path, &PipelineConfig::default()).unwrap_err();
+        assert!(err.to_string().contains("2 instruments"), "{err}");
+        let streamed = PipelineConfig { stream_batch_size: Some(7), ..Default::default() };
+        assert!(process_contract_timeframes(&path, &streamed).is_err());
+    }
+
+    #[test]
+    fn test_append_matches_full_run() {
+        // Fri Nov 18 2022 12:00 UTC over the weekend, so the stored head spans an earlier week
+        let start = 1668772800000i64;
+        let bars = |minutes: i64| minute_bars("CLF3", 1, start, 0..minutes, |m| {
+            let px = (10_000 + (m * 7919) % 300) * 10_000_000;
+            [px, px + 10_000_000, px - 10_000_000, px, 1 + m % 5]
+        });
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("CLF3.json");
+        let validation = ValidationConfig { report_dir: Some(dir.path().join("reports")), ..Default::default() };
+        let config = PipelineConfig { local_time: true, tick_specs: Some(SpecTable::cme_defaults()), validation: Some(validation), ..Default::default() };
+
+        std::fs::write(&path, bars(3 * 1440 + 2)).unwrap(); // ends inside a bucket
+        let stored = process_contract_timeframes(&path, &config).unwrap();
+        std::fs::write(&path, bars(4 * 1440)).unwrap();
+        let full = process_contract_timeframes(&path, &config).unwrap();
+        let appended = append_contract_timeframes(&path, &stored, &config).unwrap();
+        assert!(appended[0].1.equals_missing(&full[0].1));
+
+        // Only the week of the last stored bar (from Mon Nov 21 00:00 ET) was validated again
+        let report: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(dir.path().join("reports/CLF3.validation.json")).unwrap()).unwrap();
+        assert_eq!(report["rows"].as_u64(), Some(31 * 60));
+    }
+
+    #[test]
+    fn test_off_tick_minute_inside_bar() {
+        // Minute 2 closes between ticks, but the 5-minute OHLC only sees on-grid prices
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("CLF3.json");
+        let close = |m| if m == 2 { 85_595_000_000 } else { 85_590_000_000 };
+        std::fs::write(&path, minute_bars("CLF3", 1, T0, 0..10, |m| [85_590_000_000, 85_600_000_000, 85_580_000_000, close(m), 1])).unwrap();
+        let config = PipelineConfig { tick_specs: Some(SpecTable::cme_defaults()), ..Default::default() };
+        let streamed = PipelineConfig { stream_batch_size: Some(3), ..config.clone() };
+        let flags = |df: &DataFrame| -> Vec<bool> { df.column(instruments::OFF_TICK_COL).unwrap().bool().unwrap().into_no_null_iter().collect() };
+        let (_, split) = &process_instruments(&path, &config, &SymbolFilter::default()).unwrap()[0].1[0];
+        assert_eq!(flags(split), vec![true, false]);
+        for config in [config, streamed] {
+            let (_, df) = process_contract_timeframes(&path, &config).unwrap().swap_remove(0);
+            assert_eq!(flags(&df), vec![true, false]);
+        }
+    }
+
+    #[test]
+    fn test_dedupe_across_overlapping_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, minutes: std::ops::Range<i64>, conflict: i64| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, minute_bars("CLF3", 1, T0, minutes, |m| [1, 2, 1, if m == conflict { 2 } else { 1 }, 1])).unwrap();
+            path
+        };
+        // b.json re-downloads minutes 5..10 of a.json, with a differing bar at minute 7
+        let a = write("a.json", 0..10, -1);
+        let b = write("b.json", 5..20, 7);
+        let other = dir.path().join("c.json");
+        std::fs::write(&other, minute_bars("CLG3", 2, T0, 0..1, flat)).unwrap();
+
+        let config = PipelineConfig { interval: "1m".into(), ..Default::default() };
+        let overlaps = overlap_index(&[a.clone(), b.clone(), other], &config).unwrap();
+        assert_eq!(overlaps.overlaps(&a), vec![b]);
+        let dedupe = DedupeConfig { report_dir: Some(dir.path().join("reports")), overlaps, ..Default::default() };
+        let config = PipelineConfig { dedupe: Some(dedupe.clone()), ..config };
+        let (_, df) = process_contract_timeframes(&a, &config).unwrap().swap_remove(0);
+        assert_eq!(df.height(), 20);
+        let report: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(dir.path().join("reports/a.dedupe.json")).unwrap()).unwrap();
+        assert_eq!((report["input"].as_u64(), report["exact_duplicates"].as_u64()), (Some(25), Some(4)));
+        assert_eq!(report["conflicts"].as_array().unwrap().len(), 1);
+
+        let strict = DedupeConfig { policy: dedupe::DedupePolicy::Error, ..dedupe };
+        let config = PipelineConfig { dedupe: Some(strict), ..config };
+        assert!(process_contract_timeframes(&a, &config).is_err());
+    }
+
+    #[test]
+    fn test_csv_raw_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("CLF3.csv");
+        let rows: String = (0..20).map(|m| format!("{},85.59,85.6,85.58,85.59,1\n", T0 + m * 60_000)).collect();
+        std::fs::write(&path, format!("ts,open,high,low,close,volume\n{rows}")).unwrap();
+        assert!(process_contract_timeframes(&path, &PipelineConfig::default()).is_err());
+
+        let csv = CsvConfig { timestamp_format: csv_loader::TimestampFormat::EpochMillis, instrument_name: "CLF3".into(), ..Default::default() };
+        let config = PipelineConfig { csv: Some(csv), ..Default::default() };
+        let (_, df) = process_contract_timeframes(&path, &config).unwrap().swap_remove(0);
+        assert_eq!(df.height(), 4);
+        let streamed = PipelineConfig { stream_batch_size: Some(3), ..config };
+        let (_, streamed) = process_contract_timeframes(&path, &streamed).unwrap().swap_remove(0);
+        assert!(streamed.equals_missing(&df));
+    }
+}
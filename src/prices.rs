@@ -0,0 +1,226 @@
+// Representation of the OHLC price columns
+//
+// Raw prices are i64 fixed point in units of 1e-9. The pipeline carries them as
+// f64 dollars, which round-trips every raw value below 2^51 (prices up to about
+// 2.25 million) without loss, so at output they can be restored exactly as Int64
+// fixed point or Decimal, or scaled to f64 by a configurable divisor. Values
+// produced by arithmetic (ratio back-adjustment) are rounded to 1e-9 instead.
+// Only OHLC columns are converted; indicator columns stay f64 dollars. The
+// chosen format is stored in Parquet key/value metadata (`price_format`,
+// `price_scale`) so readers know how to interpret the price columns.
+
+use anyhow::{bail, Context, Result};
+use polars::prelude::*;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// Raw price units per 1.0 of price
+pub const FIXED_POINT_SCALE: i64 = 1_000_000_000;
+
+pub const PRICE_FORMAT_KEY: &str = "price_format";
+/// Stored value / `price_scale` = price
+pub const PRICE_SCALE_KEY: &str = "price_scale";
+
+pub const PRICE_COLUMNS: [&str; 4] = ["open", "high", "low", "close"];
+
+/// Largest raw magnitude whose f64 dollar value converts back to the same integer
+const MAX_EXACT_RAW: f64 = (1u64 << 51) as f64;
+
+const DECIMAL_PRECISION: usize = 38;
+const MAX_DECIMAL_SCALE: usize = 18;
+
+/// How price columns are stored
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PriceFormat {
+    /// f64 of raw / `divisor`; the default 1e9 gives prices in dollars
+    Float { divisor: f64 },
+    /// Raw Int64 in units of 1e-9
+    FixedPoint,
+    /// `Decimal(38, scale)`, rounded half away from zero when `scale` < 9
+    Decimal { scale: usize },
+}
+
+impl Default for PriceFormat {
+    fn default() -> Self {
+        Self::Float { divisor: FIXED_POINT_SCALE as f64 }
+    }
+}
+
+impl FromStr for PriceFormat {
+    type Err = anyhow::Error;
+
+    /// `float`, `float:<divisor>`, `fixed`, `decimal` or `decimal:<scale>`
+    fn from_str(s: &str) -> Result<Self> {
+        let (kind, arg) = match s.split_once(':') {
+            Some((kind, arg)) => (kind, Some(arg)),
+            None => (s, None),
+        };
+        let format = match (kind, arg) {
+            ("float", None) => Self::default(),
+            ("float", Some(d)) => Self::Float { divisor: d.parse().with_context(|| format!("Invalid price divisor '{d}'"))? },
+            ("fixed", None) => Self::FixedPoint,
+            ("decimal", None) => Self::Decimal { scale: 9 },
+            ("decimal", Some(n)) => Self::Decimal { scale: n.parse().with_context(|| format!("Invalid decimal scale '{n}'"))? },
+            _ => bail!("Unknown price format '{s}' (expected float[:divisor], fixed or decimal[:scale])"),
+        };
+        match format {
+            Self::Float { divisor } if !(divisor.is_finite() && divisor > 0.0) => bail!("Price divisor must be positive, got {divisor}"),
+            Self::Decimal { scale } if scale > MAX_DECIMAL_SCALE => bail!("Decimal scale must be at most {MAX_DECIMAL_SCALE}, got {scale}"),
+            _ => Ok(format),
+        }
+    }
+}
+
+impl fmt::Display for PriceFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Float { .. } => write!(f, "float"),
+            Self::FixedPoint => write!(f, "fixed"),
+            Self::Decimal { scale } => write!(f, "decimal:{scale}"),
+        }
+    }
+}
+
+/// Rescale a 1e-9 fixed-point value to `scale` decimal digits
+fn rescale(raw: i64, scale: usize) -> i128 {
+    let raw = raw as i128;
+    if scale >= 9 {
+        return raw * 10i128.pow((scale - 9) as u32);
+    }
+    let div = 10i128.pow((9 - scale) as u32);
+    let q = raw / div;
+    let r = raw % div;
+    if 2 * r.abs() >= div { q + raw.signum() } else { q }
+}
+
+impl PriceFormat {
+    /// Stored value / `price_scale()` = price
+    pub fn price_scale(&self) -> f64 {
+        match self {
+            Self::Float { divisor } => FIXED_POINT_SCALE as f64 / divisor,
+            Self::FixedPoint => FIXED_POINT_SCALE as f64,
+            Self::Decimal { .. } => 1.0,
+        }
+    }
+
+    /// Build a price column from raw 1e-9 fixed-point values
+    pub fn from_raw(&self, name: &str, raw: &Int64Chunked) -> Result<Series> {
+        let series = match self {
+            Self::Float { divisor } => raw.iter().map(|v| v.map(|v| v as f64 / divisor)).collect::<Float64Chunked>().into_series(),
+            Self::FixedPoint => raw.clone().into_series(),
+            Self::Decimal { scale } => raw
+                .iter()
+                .map(|v| v.map(|v| rescale(v, *scale)))
+                .collect::<Int128Chunked>()
+                .into_decimal(Some(DECIMAL_PRECISION), *scale)?
+                .into_series(),
+        };
+        Ok(series.with_name(name.into()))
+    }
+
+    /// Parquet key/value metadata describing this format
+    pub fn metadata(&self) -> Vec<(String, String)> {
+        vec![
+            (PRICE_FORMAT_KEY.to_string(), self.to_string()),
+            (PRICE_SCALE_KEY.to_string(), self.price_scale().to_string()),
+        ]
+    }
+
+    /// Recover the format from Parquet metadata; files without it hold dollar f64 prices
+    pub fn from_metadata(metadata: &BTreeMap<String, String>) -> Result<Self> {
+        let Some(format) = metadata.get(PRICE_FORMAT_KEY) else { return Ok(Self::default()) };
+        match format.parse()? {
+            Self::Float { .. } => {
+                let scale: f64 = metadata
+                    .get(PRICE_SCALE_KEY)
+                    .context("Float price format without a price_scale")?
+                    .parse()?;
+                Ok(Self::Float { divisor: FIXED_POINT_SCALE as f64 / scale })
+            }
+            other => Ok(other),
+        }
+    }
+}
+
+/// Price columns of `df`: OHLC plus any `<price>_unadj` columns from stitching
+fn price_columns(df: &DataFrame) -> Vec<String> {
+    let names = df.get_column_names();
+    PRICE_COLUMNS
+        .iter()
+        .flat_map(|c| [c.to_string(), format!("{c}_unadj")])
+        .filter(|c| names.iter().any(|n| n.as_str() == c))
+        .collect()
+}
+
+/// Convert dollar f64 price columns to `format`
+///
+/// Fails on prices too large to recover their raw 1e-9 value exactly.
+pub fn apply_price_format(df: &DataFrame, format: &PriceFormat) -> Result<DataFrame> {
+    let mut out = df.clone();
+    if *format == PriceFormat::default() {
+        return Ok(out);
+    }
+    for name in price_columns(df) {
+        let dollars = df.column(&name)?.cast(&DataType::Float64)?;
+        let raw: Int64Chunked = dollars
+            .f64()?
+            .iter()
+            .map(|v| {
+                v.map(|v| {
+                    let raw = (v * FIXED_POINT_SCALE as f64).round();
+                    if raw.is_finite() && raw.abs() < MAX_EXACT_RAW {
+                        Ok(raw as i64)
+                    } else {
+                        bail!("Price {v} in column '{name}' is out of range for exact {format} output")
+                    }
+                })
+                .transpose()
+            })
+            .collect::<Result<_>>()?;
+        out.with_column(format.from_raw(&name, &raw)?)?;
+    }
+    Ok(out)
+}
+
+/// Convert price columns stored in `format` back to dollar f64
+pub fn to_float_prices(df: &DataFrame, format: &PriceFormat) -> Result<DataFrame> {
+    let mut out = df.clone();
+    let scale = format.price_scale();
+    for name in price_columns(df) {
+        let values = df.column(&name)?.cast(&DataType::Float64)?;
+        let dollars = values.f64()?.apply_values(|v| v / scale);
+        out.with_column(dollars.into_series().with_name(name.as_str().into()))?;
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_formats_roundtrip() {
+        let df = df!("open" => [85.59, 85.6], "close" => [85.61, 85.005], "volume" => [1.0, 2.0]).unwrap();
+        for spec in ["fixed", "decimal", "decimal:2", "float:1e7"] {
+            let format: PriceFormat = spec.parse().unwrap();
+            let stored = apply_price_format(&df, &format).unwrap();
+            let meta: BTreeMap<_, _> = format.metadata().into_iter().collect();
+            let back = to_float_prices(&stored, &PriceFormat::from_metadata(&meta).unwrap()).unwrap();
+            let close = back.column("close").unwrap().f64().unwrap().get(1).unwrap();
+            let expected = if spec == "decimal:2" { 85.01 } else { 85.005 };
+            assert!((close - expected).abs() < 1e-12, "{spec}: {close}");
+        }
+        let fixed = apply_price_format(&df, &PriceFormat::FixedPoint).unwrap();
+        assert_eq!(fixed.column("open").unwrap().i64().unwrap().get(0), Some(85_590_000_000));
+        assert!(matches!(apply_price_format(&df, &"decimal:4".parse().unwrap()).unwrap().column("open").unwrap().dtype(), DataType::Decimal(_, Some(4))));
+        assert!("decimal:40".parse::<PriceFormat>().is_err());
+
+        // Raw values survive the f64 dollar round trip bit for bit
+        let raw = [1i64, 85_590_000_001, 2_000_000_123_456_789];
+        let dollars = df!("close" => raw.map(|r| r as f64 / FIXED_POINT_SCALE as f64)).unwrap();
+        let exact = apply_price_format(&dollars, &PriceFormat::FixedPoint).unwrap();
+        assert_eq!(exact.column("close").unwrap().i64().unwrap().into_no_null_iter().collect::<Vec<_>>(), raw);
+        assert!(apply_price_format(&df!("close" => [3e6]).unwrap(), &PriceFormat::FixedPoint).is_err());
+    }
+}
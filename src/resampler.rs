@@ -1,43 +1,102 @@
+use crate::gaps::is_open_minute;
+use crate::indicators::{day_session_id, night_session_id, session_start};
+use crate::instruments::OFF_TICK_COL;
 use crate::loader::Bar;
+use crate::prices::{PriceFormat, PRICE_COLUMNS};
+use crate::timestamps::{millis_to_timestamp, normalize_timestamp, time_column, timestamp_dtype, timestamp_millis};
+use chrono::NaiveDate;
 use polars::prelude::*;
 use anyhow::Result;
+use std::str::FromStr;
 
+pub(crate) const NANOS_PER_MILLI: i64 = 1_000_000;
+
+pub const INSTRUMENT_ID_COL: &str = "instrument_id";
+pub const INSTRUMENT_NAME_COL: &str = "instrument_name";
+
+const OHLCV_COLUMNS: [&str; 6] = ["timestamp", "open", "high", "low", "close", "volume"];
+
+/// Instrument metadata columns present in `df`, used as resampling group keys
+fn instrument_keys(df: &DataFrame) -> Vec<&'static str> {
+    [INSTRUMENT_ID_COL, INSTRUMENT_NAME_COL]
+        .into_iter()
+        .filter(|k| df.get_column_names().iter().any(|c| c.as_str() == *k))
+        .collect()
+}
+
+/// Whether `df` carries an `off_tick` flag column to keep through resampling
+fn has_flags(df: &DataFrame) -> bool {
+    df.get_column_names().iter().any(|c| c.as_str() == OFF_TICK_COL)
+}
+
+/// OHLCV aggregations, plus the `off_tick` flag (set if any source bar was off tick)
+fn bar_aggregations(flags: bool) -> Vec<Expr> {
+    let mut aggs = vec![
+        col("open").first().alias("open"),
+        col("high").max().alias("high"),
+        col("low").min().alias("low"),
+        col("close").last().alias("close"),
+        col("volume").sum().alias("volume"),
+    ];
+    if flags {
+        aggs.push(col(OFF_TICK_COL).any(true).alias(OFF_TICK_COL));
+    }
+    aggs
+}
+
+/// OHLCV columns followed by whichever instrument columns are present, then `off_tick`
+fn output_columns(keys: &[&str], flags: bool) -> Vec<Expr> {
+    let flag = flags.then_some(&OFF_TICK_COL);
+    OHLCV_COLUMNS.iter().chain(keys).chain(flag).map(|c| col(*c)).collect()
+}
+
+/// Bars as a frame with dollar f64 prices
 pub fn bars_to_dataframe(bars: &[Bar]) -> Result<DataFrame> {
-    let ts: Vec<_> = bars.iter().map(|b| b.ts_event / 1_000_000).collect(); // nanoseconds → milliseconds
-    let open: Vec<_> = bars.iter().map(|b| b.open as f64 / 1e9).collect();   // scale to float prices
-    let high: Vec<_> = bars.iter().map(|b| b.high as f64 / 1e9).collect();
-    let low: Vec<_> = bars.iter().map(|b| b.low as f64 / 1e9).collect();
-    let close: Vec<_> = bars.iter().map(|b| b.close as f64 / 1e9).collect();
+    let format = PriceFormat::default();
+    let ts: Vec<_> = bars.iter().map(|b| b.ts_event / NANOS_PER_MILLI).collect(); // nanoseconds → milliseconds
+    let price = |name: &str, field: fn(&Bar) -> i64| -> Result<Column> {
+        let raw = Int64Chunked::from_iter_values(name.into(), bars.iter().map(field));
+        Ok(format.from_raw(name, &raw)?.into_column())
+    };
     let volume: Vec<_> = bars.iter().map(|b| b.volume as f64).collect();
+    let instrument_id: Vec<_> = bars.iter().map(|b| b.instrument_id).collect();
+    let instrument_name: Vec<_> = bars.iter().map(|b| b.instrument_name.as_str()).collect();
 
-    let df = df![
-        "timestamp" => ts,
-        "open" => open,
-        "high" => high,
-        "low" => low,
-        "close" => close,
-        "volume" => volume
-    ]?;
+    let mut df = DataFrame::new(vec![
+        Column::new("timestamp".into(), ts),
+        price("open", |b| b.open)?,
+        price("high", |b| b.high)?,
+        price("low", |b| b.low)?,
+        price("close", |b| b.close)?,
+        Column::new("volume".into(), volume),
+        Column::new(INSTRUMENT_ID_COL.into(), instrument_id),
+        Column::new(INSTRUMENT_NAME_COL.into(), instrument_name),
+    ])?;
+    normalize_timestamp(&mut df)?; // Int64 millis → Datetime(ms, "UTC")
 
     Ok(df)
 }
 
-pub fn downsample_to_5min(df: &DataFrame) -> Result<DataFrame> {
-    let lazy = df.clone().lazy();
+/// Parse a Polars duration string ("5m", "15m", "1h", "4h", "1d", ...)
+fn parse_interval(interval: &str) -> Result<Duration> {
+    let every = Duration::try_parse(interval)
+        .map_err(|e| anyhow::anyhow!("Invalid resample interval '{interval}': {e}"))?;
+    if every.is_zero() || every.negative() {
+        anyhow::bail!("Resample interval must be positive, got '{interval}'");
+    }
+    Ok(every)
+}
 
-    let grouped = lazy
-        .with_column(
-            col("timestamp")
-                .cast(DataType::Datetime(TimeUnit::Milliseconds, None))
-                .alias("timestamp"),
-        )
-        .sort(["timestamp"], SortMultipleOptions::default())
+/// Lazy OHLCV aggregation of a timestamp-sorted frame into `every`-sized buckets,
+/// separately for each instrument in `keys`
+fn resample_lazy(sorted: LazyFrame, every: Duration, keys: &[&str], flags: bool) -> LazyFrame {
+    sorted
         .group_by_dynamic(
             col("timestamp"),
-            [],
+            keys.iter().map(|k| col(*k)).collect::<Vec<_>>(),
             DynamicGroupOptions {
-                every: Duration::parse("5m"),
-                period: Duration::parse("5m"),
+                every,
+                period: every,
                 offset: Duration::parse("0s"),
                 label: Label::Left,
                 include_boundaries: false,
@@ -46,18 +105,293 @@ pub fn downsample_to_5min(df: &DataFrame) -> Result<DataFrame> {
                 ..Default::default()
             },
         )
-        .agg([
-            col("open").first().alias("open"),
-            col("high").max().alias("high"),
-            col("low").min().alias("low"),
-            col("close").last().alias("close"),
-            col("volume").sum().alias("volume"),
-        ])
+        .agg(bar_aggregations(flags))
+        .select(output_columns(keys, flags))
+        .sort_by_exprs(
+            keys.iter().take(1).map(|k| col(*k)).chain([col("timestamp")]).collect::<Vec<_>>(),
+            SortMultipleOptions::default().with_maintain_order(true),
+        )
+}
+
+fn sorted_lazy(df: &DataFrame) -> Result<LazyFrame> {
+    let mut df = df.clone();
+    normalize_timestamp(&mut df)?;
+    Ok(df.lazy().sort(["timestamp"], SortMultipleOptions::default()))
+}
+
+/// Resample bars to any Polars duration interval, e.g. "5m", "15m", "1h" or "4h",
+/// or to session bars ("globex", "day", "night", see [`SessionKind`])
+pub fn resample(df: &DataFrame, interval: &str) -> Result<DataFrame> {
+    if let Ok(kind) = interval.parse() {
+        return resample_sessions(df, kind);
+    }
+    let every = parse_interval(interval)?;
+    Ok(resample_lazy(sorted_lazy(df)?, every, &instrument_keys(df), has_flags(df)).collect()?)
+}
+
+/// Resample one frame to several intervals at once, sorting the input only once
+///
+/// Results are returned in the order of `intervals`.
+pub fn resample_many(df: &DataFrame, intervals: &[&str]) -> Result<Vec<DataFrame>> {
+    let keys = instrument_keys(df);
+    let sorted = sorted_lazy(df)?.collect()?.lazy();
+    let mut out: Vec<Option<DataFrame>> = vec![None; intervals.len()];
+    let mut lazy = Vec::new(); // (position, frame) of the duration intervals
+    for (i, interval) in intervals.iter().enumerate() {
+        match interval.parse() {
+            Ok(kind) => out[i] = Some(resample_sessions(df, kind)?),
+            Err(_) => lazy.push((i, resample_lazy(sorted.clone(), parse_interval(interval)?, &keys, has_flags(df)))),
+        }
+    }
+    let (positions, frames): (Vec<usize>, Vec<LazyFrame>) = lazy.into_iter().unzip();
+    for (i, frame) in positions.into_iter().zip(collect_all(frames)?) {
+        out[i] = Some(frame);
+    }
+    Ok(out.into_iter().flatten().collect())
+}
+
+pub fn downsample_to_5min(df: &DataFrame) -> Result<DataFrame> {
+    resample(df, "5m")
+}
+
+/// What to emit for in-session buckets without trades
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FillPolicy {
+    #[default]
+    Leave, // omit the bucket
+    Flat,  // previous close as OHLC, zero volume
+    Null,  // row with null prices and volume
+}
+
+impl FromStr for FillPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "leave" => Ok(Self::Leave),
+            "flat" => Ok(Self::Flat),
+            "null" => Ok(Self::Null),
+            other => anyhow::bail!("Unknown fill policy '{other}' (expected leave, flat or null)"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FillConfig {
+    pub policy: FillPolicy,
+    pub holidays: Vec<NaiveDate>, // trade dates without a Globex session
+}
+
+/// Bucket length in millis of a fixed interval shorter than a week
+fn fixed_interval_millis(interval: &str) -> Result<i64> {
+    let every = parse_interval(interval)?;
+    if every.months() != 0 || every.weeks() != 0 {
+        anyhow::bail!("Interval must be fixed and shorter than a week, got '{interval}'");
+    }
+    Ok(every.days() * 86_400_000 + every.nanoseconds() / NANOS_PER_MILLI)
+}
+
+/// Check that `target` bars can be built by resampling `base` bars
+///
+/// Buckets start at whole multiples of their length since the epoch, so a `target`
+/// that is a multiple of `base` always starts and ends on `base` boundaries.
+///
+/// Session boundaries fall on whole ET hours, so session bars need a `base` dividing an hour.
+pub fn ensure_derivable(base: &str, target: &str) -> Result<()> {
+    let b = fixed_interval_millis(base)?;
+    let t = if target.parse::<SessionKind>().is_ok() { 3_600_000 } else { fixed_interval_millis(target)? };
+    anyhow::ensure!(b > 0 && t % b == 0, "Interval '{target}' is not a multiple of '{base}', so it cannot be built from '{base}' bars");
+    Ok(())
+}
+
+/// Whether any minute of the bucket `[start, start + len)` is inside a Globex session
+fn bucket_in_session(start: i64, len: i64, holidays: &[NaiveDate]) -> bool {
+    (start..start + len).step_by(60_000).any(|minute| is_open_minute(minute, holidays))
+}
+
+/// Insert rows for missing in-session buckets of a resampled frame
+///
+/// Buckets between each instrument's first and last bar that overlap a Globex session
+/// are filled per `config.policy`; the maintenance break, weekends and holidays stay
+/// empty. Instrument columns are carried from the previous bar; any other columns
+/// (e.g. indicators) are null on filled rows. Session bars are never filled.
+pub fn fill_gaps(df: &DataFrame, interval: &str, config: &FillConfig) -> Result<DataFrame> {
+    if config.policy == FillPolicy::Leave || df.height() == 0 || interval.parse::<SessionKind>().is_ok() {
+        return Ok(df.clone());
+    }
+    let bucket = fixed_interval_millis(interval)?;
+    let ts = timestamp_millis(df)?;
+    let ids = match df.column(INSTRUMENT_ID_COL) {
+        Ok(c) => Some(c.cast(&DataType::UInt32)?.u32()?.clone()),
+        Err(_) => None,
+    };
+
+    // Source row of every output row (None = filled) and the bar each row follows
+    let (mut source, mut previous, mut times) = (Vec::new(), Vec::new(), Vec::new());
+    for row in 0..df.height() {
+        let t = ts.get(row).unwrap_or_default();
+        let same_instrument = row > 0 && ids.as_ref().is_none_or(|ids| ids.get(row) == ids.get(row - 1));
+        if same_instrument {
+            let prev_t = ts.get(row - 1).unwrap_or_default();
+            let mut missing = prev_t + bucket;
+            while missing < t {
+                if bucket_in_session(missing, bucket, &config.holidays) {
+                    source.push(None);
+                    previous.push(Some((row - 1) as IdxSize));
+                    times.push(missing);
+                }
+                missing += bucket;
+            }
+        }
+        source.push(Some(row as IdxSize));
+        previous.push(Some(row as IdxSize));
+        times.push(t);
+    }
+    if source.len() == df.height() {
+        return Ok(df.clone());
+    }
+
+    let source: IdxCa = source.into_iter().collect();
+    let previous: IdxCa = previous.into_iter().collect();
+    let original = source.is_not_null();
+    let mut out = df.take(&source)?;
+    out.with_column(millis_to_timestamp(time_column(df), Int64Chunked::from_vec("ts".into(), times))?)?;
+    for key in instrument_keys(df) {
+        out.with_column(df.column(key)?.take(&previous)?)?;
+    }
+    if config.policy == FillPolicy::Flat {
+        let prev_close = df.column("close")?.as_materialized_series().take(&previous)?;
+        for name in PRICE_COLUMNS {
+            let filled = out.column(name)?.as_materialized_series().zip_with(&original, &prev_close)?;
+            out.with_column(filled.with_name(name.into()))?;
+        }
+        let volume = out.column("volume")?.as_materialized_series().clone();
+        let zeros = Series::full_null("volume".into(), volume.len(), volume.dtype()).fill_null(FillNullStrategy::Zero)?;
+        out.with_column(volume.zip_with(&original, &zeros)?)?;
+    }
+    Ok(out)
+}
+
+/// Exchange session used to bucket bars (CL, America/New_York hours)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionKind {
+    TradingDay,   // 18:00 - 17:00 next day
+    DaySession,   // 08:00 - 17:00
+    NightSession, // 18:00 - 08:00 next day
+}
+
+impl FromStr for SessionKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "globex" => Ok(Self::TradingDay),
+            "day" => Ok(Self::DaySession),
+            "night" => Ok(Self::NightSession),
+            other => anyhow::bail!("Unknown session '{other}' (expected globex, day or night)"),
+        }
+    }
+}
+
+/// Resample bars into one bar per exchange session
+///
+/// Buckets follow ET wall-clock session boundaries, so they stay correct across DST
+/// changes. Each bar is labelled with its session start; bars outside the requested
+/// session (e.g. day hours for `NightSession`) are dropped.
+pub fn resample_sessions(df: &DataFrame, kind: SessionKind) -> Result<DataFrame> {
+    let mut sorted = df.sort(["timestamp"], SortMultipleOptions::default())?;
+    let keys: Int64Chunked = timestamp_millis(&sorted)?
+        .into_iter()
+        .map(|t| {
+            t.and_then(|t| match kind {
+                SessionKind::TradingDay => Some(session_start(t)),
+                SessionKind::DaySession => day_session_id(t),
+                SessionKind::NightSession => night_session_id(t),
+            })
+        })
+        .collect();
+    sorted.with_column(keys.with_name("session".into()))?;
+    let instruments = instrument_keys(df);
+
+    let grouped = sorted
+        .lazy()
+        .filter(col("session").is_not_null())
+        .group_by_stable(instruments.iter().map(|k| col(*k)).chain([col("session")]).collect::<Vec<_>>())
+        .agg(bar_aggregations(has_flags(df)))
+        .with_column(
+            col("session")
+                .cast(timestamp_dtype())
+                .alias("timestamp"),
+        )
+        .select(output_columns(&instruments, has_flags(df)))
         .collect()?;
 
     Ok(grouped)
 }
 
+/// Resample a time-ordered stream of bar batches to `interval` bars
+///
+/// Only one partially filled bucket is carried between batches, so memory is bounded
+/// by the batch size plus the (much smaller) resampled output. The interval must be
+/// a fixed length of less than a week.
+pub fn resample_stream<I>(batches: I, interval: &str) -> Result<DataFrame>
+where
+    I: IntoIterator<Item = Result<Vec<Bar>>>,
+{
+    resample_stream_with(batches, interval, |_| Ok(()))
+}
+
+/// Like [`resample_stream`], but run `prepare` on each chunk's 1-minute frame before
+/// it is resampled, e.g. to flag off-tick prices
+pub fn resample_stream_with<I, F>(batches: I, interval: &str, mut prepare: F) -> Result<DataFrame>
+where
+    I: IntoIterator<Item = Result<Vec<Bar>>>,
+    F: FnMut(&mut DataFrame) -> Result<()>,
+{
+    let bucket_ns = fixed_interval_millis(interval)? * NANOS_PER_MILLI;
+    let mut carry: Vec<Bar> = Vec::new();
+    let mut out: Option<DataFrame> = None;
+    let mut emit = |bars: &[Bar]| -> Result<()> {
+        if bars.is_empty() {
+            return Ok(());
+        }
+        let mut minutes = bars_to_dataframe(bars)?;
+        prepare(&mut minutes)?;
+        let down = resample(&minutes, interval)?;
+        match out.as_mut() {
+            Some(df) => { df.vstack_mut(&down)?; }
+            None => out = Some(down),
+        }
+        Ok(())
+    };
+
+    for batch in batches {
+        carry.extend(batch?);
+        let Some(last) = carry.last() else { continue };
+        let open_bucket = last.ts_event.div_euclid(bucket_ns);
+        let split = carry.partition_point(|b| b.ts_event.div_euclid(bucket_ns) < open_bucket);
+        let rest = carry.split_off(split);
+        emit(&carry)?;
+        carry = rest;
+    }
+    emit(&carry)?;
+
+    match out {
+        Some(df) if !instrument_keys(&df).is_empty() => {
+            Ok(df.sort([INSTRUMENT_ID_COL, "timestamp"], SortMultipleOptions::default().with_maintain_order(true))?)
+        }
+        other => Ok(other.unwrap_or_default()),
+    }
+}
+
+/// Downsample a time-ordered stream of bar batches to 5-minute bars
+pub fn downsample_stream_to_5min<I>(batches: I) -> Result<DataFrame>
+where
+    I: IntoIterator<Item = Result<Vec<Bar>>>,
+{
+    resample_stream(batches, "5m")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -76,4 +410,111 @@ mod tests {
         let down = downsample_to_5min(&df).unwrap();
         assert_eq!(down.height(), 2);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_stream_matches_in_memory() {
+        let bars = crate::loader::load_bars_from_file("raw_data/sample.json").unwrap();
+        let expected = downsample_to_5min(&bars_to_dataframe(&bars).unwrap()).unwrap();
+        let batches = crate::loader::stream_bars_from_file("raw_data/sample.json", 7).unwrap();
+        let streamed = downsample_stream_to_5min(batches).unwrap();
+        assert!(streamed.equals(&expected));
+    }
+
+    #[test]
+    fn test_resample_many() {
+        let bars = crate::loader::load_bars_from_file("raw_data/sample.json").unwrap();
+        let df = bars_to_dataframe(&bars).unwrap();
+        let frames = resample_many(&df, &["5m", "15m", "1h"]).unwrap();
+        assert!(frames[0].equals(&downsample_to_5min(&df).unwrap()));
+        assert_eq!(frames[2].column("timestamp").unwrap().dtype(), &timestamp_dtype());
+        assert!(frames[1].equals(&resample(&df, "15m").unwrap()));
+        let hourly = &frames[2];
+        assert!(hourly.height() < frames[1].height());
+        let total = |d: &DataFrame| d.column("volume").unwrap().f64().unwrap().sum().unwrap();
+        assert_eq!(total(hourly), total(&df));
+        assert!(resample(&df, "bogus").is_err());
+
+        assert!(ensure_derivable("5m", "15m").is_ok() && ensure_derivable("5m", "1d").is_ok());
+        assert!(ensure_derivable("5m", "7m").is_err() && ensure_derivable("5m", "1mo").is_err());
+        assert!(ensure_derivable("15m", "globex").is_ok() && ensure_derivable("7m", "night").is_err());
+
+        let mixed = resample_many(&df, &["globex", "15m", "day"]).unwrap();
+        assert!(mixed[0].equals(&resample_sessions(&df, SessionKind::TradingDay).unwrap()));
+        assert!(mixed[1].equals(&frames[1]));
+        assert!(mixed[2].equals(&resample(&df, "day").unwrap()));
+    }
+
+    #[test]
+    fn test_session_bars_across_dst() {
+        use chrono::TimeZone;
+        use chrono_tz::America::New_York;
+        let et = |d: u32, h: u32| New_York.with_ymd_and_hms(2022, 11, d, h, 0, 0).unwrap().timestamp_millis();
+        // Thu/Fri before the Nov 6 DST change, then Sun night / Mon after it
+        let ts = vec![et(3, 19), et(4, 9), et(4, 16), et(6, 18), et(7, 3), et(7, 10)];
+        let df = df!(
+            "timestamp" => ts,
+            "open" => [1.0, 2.0, 3.0, 4.0, 5.0, 6.0],
+            "high" => [1.0, 2.0, 3.0, 4.0, 5.0, 6.0],
+            "low" => [1.0, 2.0, 3.0, 4.0, 5.0, 6.0],
+            "close" => [1.0, 2.0, 3.0, 4.0, 5.0, 6.0],
+            "volume" => [1.0; 6]
+        ).unwrap();
+
+        let daily = resample_sessions(&df, SessionKind::TradingDay).unwrap();
+        assert_eq!(daily.column("timestamp").unwrap().dtype(), &timestamp_dtype());
+        let starts: Vec<i64> = daily.column("timestamp").unwrap().cast(&DataType::Int64).unwrap().i64().unwrap().into_no_null_iter().collect();
+        assert_eq!(starts, vec![et(3, 18), et(6, 18)]);
+        assert_eq!(starts[0] % 86_400_000, 22 * 3_600_000); // 18:00 EDT
+        assert_eq!(starts[1] % 86_400_000, 23 * 3_600_000); // 18:00 EST
+        assert_eq!(daily.column("close").unwrap().f64().unwrap().get(0), Some(3.0));
+
+        let night = resample_sessions(&df, SessionKind::NightSession).unwrap();
+        assert_eq!(night.column("volume").unwrap().f64().unwrap().into_no_null_iter().collect::<Vec<_>>(), vec![1.0, 2.0]);
+        let day = resample_sessions(&df, SessionKind::DaySession).unwrap();
+        assert_eq!(day.height(), 2);
+    }
+
+    #[test]
+    fn test_resample_groups_by_instrument() {
+        let bar = |id: u32, name: &str, minute: i64, px: i64| Bar {
+            instrument_name: name.into(), instrument_id: id, ts_event: minute * 60_000_000_000,
+            open: px, high: px, low: px, close: px, volume: 1,
+        };
+        let bars = vec![
+            bar(1, "CLF3", 0, 80), bar(2, "CLG3", 0, 90),
+            bar(1, "CLF3", 1, 81), bar(2, "CLG3", 1, 91),
+            bar(1, "CLF3", 5, 82),
+        ];
+        let down = downsample_to_5min(&bars_to_dataframe(&bars).unwrap()).unwrap();
+        assert_eq!(down.height(), 3);
+        let names: Vec<_> = down.column(INSTRUMENT_NAME_COL).unwrap().str().unwrap().into_no_null_iter().collect();
+        assert_eq!(names, vec!["CLF3", "CLF3", "CLG3"]);
+        let close = down.column("close").unwrap().f64().unwrap();
+        assert_eq!((close.get(0), close.get(2)), (Some(81e-9), Some(91e-9)));
+    }
+
+    #[test]
+    fn test_fill_gaps_in_session_only() {
+        use chrono::TimeZone;
+        use chrono_tz::America::New_York;
+        let et = |h: u32, m: u32| New_York.with_ymd_and_hms(2022, 11, 14, h, m, 0).unwrap().timestamp_millis() * NANOS_PER_MILLI;
+        // Monday 16:45, 16:55, then 18:05 after the maintenance break
+        let bars: Vec<Bar> = [(16, 45, 80), (16, 55, 81), (18, 5, 82)]
+            .into_iter()
+            .map(|(h, m, px)| Bar { instrument_name: "CLF3".into(), instrument_id: 1, ts_event: et(h, m), open: px, high: px, low: px, close: px, volume: 2 })
+            .collect();
+        let down = downsample_to_5min(&bars_to_dataframe(&bars).unwrap()).unwrap();
+
+        let flat = fill_gaps(&down, "5m", &FillConfig { policy: FillPolicy::Flat, ..Default::default() }).unwrap();
+        assert_eq!(flat.height(), 5); // + 16:50 and 18:00; 17:00-17:55 stays empty
+        let close: Vec<_> = flat.column("close").unwrap().f64().unwrap().into_no_null_iter().collect();
+        assert_eq!(close, vec![80e-9, 80e-9, 81e-9, 81e-9, 82e-9]);
+        let volume: Vec<_> = flat.column("volume").unwrap().f64().unwrap().into_no_null_iter().collect();
+        assert_eq!(volume, vec![2.0, 0.0, 2.0, 0.0, 2.0]);
+        assert_eq!(flat.column(INSTRUMENT_NAME_COL).unwrap().null_count(), 0);
+
+        let null = fill_gaps(&down, "5m", &FillConfig { policy: FillPolicy::Null, ..Default::default() }).unwrap();
+        assert_eq!((null.height(), null.column("open").unwrap().null_count()), (5, 2));
+        assert_eq!(null.column("timestamp").unwrap().dtype(), &timestamp_dtype());
+    }
+}
@@ -0,0 +1,231 @@
+// Load contract rollover windows from a JSON calendar file
+//
+// Boundaries are written as America/New_York wall-clock times so the file
+// can be edited by hand, and are converted to UTC epoch millis on load.
+
+use anyhow::{bail, Context, Result};
+use chrono::{NaiveDateTime, TimeZone};
+use chrono_tz::America::New_York;
+use polars::prelude::DataFrame;
+use serde::{Deserialize, Serialize};
+use std::fs::{create_dir_all, File};
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::schedule::RollRule;
+use crate::stitcher::ContractWindow;
+
+const ET_FORMAT: &str = "%Y-%m-%d %H:%M";
+
+/// Default largest allowed hole between two consecutive windows (the 16:59 -> 18:00 break)
+pub const DEFAULT_MAX_GAP_MINUTES: i64 = 61;
+
+/// One contract entry as written in the calendar file
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct RawEntry {
+    name: String,
+    start: String, // ET wall clock, "YYYY-MM-DD HH:MM"
+    end: String,   // ET wall clock, exclusive
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RawCalendar {
+    #[serde(default)]
+    max_gap_minutes: Option<i64>,
+    contracts: Vec<RawEntry>,
+}
+
+/// Where the stitcher's roll points come from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RollMode {
+    #[default]
+    Calendar, // the windows of the calendar file
+    Volume,   // the calendar's contracts, rolled where the back month's session volume overtakes
+    Rules { sessions_before_expiry: u32 }, // generated from the CL expiry rules for the raw files
+}
+
+impl FromStr for RollMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "calendar" => Ok(Self::Calendar),
+            "volume" => Ok(Self::Volume),
+            "rules" => Ok(Self::Rules { sessions_before_expiry: RollRule::default().sessions_before_expiry }),
+            other => match other.strip_prefix("rules:") {
+                Some(n) => Ok(Self::Rules {
+                    sessions_before_expiry: n.parse().with_context(|| format!("Invalid roll offset '{n}' in --roll"))?,
+                }),
+                None => bail!("Unknown roll mode '{other}' (expected calendar, volume or rules[:N])"),
+            },
+        }
+    }
+}
+
+/// A validated rollover window for one contract file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RolloverWindow {
+    pub name: String,  // file stem of the contract
+    pub start_ts: i64, // UNIX epoch millis
+    pub end_ts: i64,   // exclusive
+}
+
+impl RolloverWindow {
+    /// Pair this window with the contract's data for stitching
+    pub fn to_contract_window(&self, df: DataFrame) -> ContractWindow {
+        ContractWindow {
+            name: self.name.clone(),
+            df,
+            start_ts: self.start_ts,
+            end_ts: self.end_ts,
+        }
+    }
+}
+
+/// Ordered list of rollover windows
+#[derive(Debug, Clone)]
+pub struct RolloverCalendar {
+    pub windows: Vec<RolloverWindow>,
+    pub max_gap_ms: i64,
+}
+
+impl RolloverCalendar {
+    /// Build a calendar from windows, sorting them and checking for overlaps and gaps
+    pub fn new(mut windows: Vec<RolloverWindow>, max_gap_minutes: i64) -> Result<Self> {
+        windows.sort_by_key(|w| w.start_ts);
+        let calendar = Self { windows, max_gap_ms: max_gap_minutes * 60_000 };
+        calendar.validate()?;
+        Ok(calendar)
+    }
+
+    /// Check each window is non-empty and consecutive windows neither overlap nor leave large holes
+    pub fn validate(&self) -> Result<()> {
+        for w in &self.windows {
+            if w.start_ts >= w.end_ts {
+                bail!("Rollover window {} ends before it starts", w.name);
+            }
+        }
+        for pair in self.windows.windows(2) {
+            let (prev, next) = (&pair[0], &pair[1]);
+            if next.start_ts < prev.end_ts {
+                bail!("Rollover windows {} and {} overlap", prev.name, next.name);
+            }
+            let gap = next.start_ts - prev.end_ts;
+            if gap > self.max_gap_ms {
+                bail!(
+                    "Gap of {} minutes between rollover windows {} and {}",
+                    gap / 60_000,
+                    prev.name,
+                    next.name
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Look up the window assigned to a contract file stem
+    pub fn get(&self, name: &str) -> Option<&RolloverWindow> {
+        self.windows.iter().find(|w| w.name == name)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Save the calendar in the same JSON format [`load_rollover_calendar`] reads
+    pub fn write_json<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let raw = RawCalendar {
+            max_gap_minutes: Some(self.max_gap_ms / 60_000),
+            contracts: self
+                .windows
+                .iter()
+                .map(|w| {
+                    Ok(RawEntry {
+                        name: w.name.clone(),
+                        start: format_et_millis(w.start_ts)?,
+                        end: format_et_millis(w.end_ts)?,
+                    })
+                })
+                .collect::<Result<_>>()?,
+        };
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create rollover calendar: {}", path.display()))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), &raw)?;
+        Ok(())
+    }
+}
+
+/// Parse an ET wall-clock time ("YYYY-MM-DD HH:MM") into UTC epoch millis
+pub fn parse_et_millis(s: &str) -> Result<i64> {
+    let naive = NaiveDateTime::parse_from_str(s.trim(), ET_FORMAT)
+        .with_context(|| format!("Invalid ET datetime '{s}', expected {ET_FORMAT}"))?;
+    let local = New_York
+        .from_local_datetime(&naive)
+        .single()
+        .with_context(|| format!("Ambiguous or non-existent ET datetime '{s}'"))?;
+    Ok(local.timestamp_millis())
+}
+
+/// Format UTC epoch millis as an ET wall-clock time ("YYYY-MM-DD HH:MM")
+pub fn format_et_millis(ms: i64) -> Result<String> {
+    let local = New_York
+        .timestamp_millis_opt(ms)
+        .single()
+        .with_context(|| format!("Timestamp {ms} is out of range"))?;
+    Ok(local.format(ET_FORMAT).to_string())
+}
+
+/// Load and validate a rollover calendar from a JSON file
+pub fn load_rollover_calendar<P: AsRef<Path>>(path: P) -> Result<RolloverCalendar> {
+    let file = File::open(&path)
+        .with_context(|| format!("Failed to open rollover calendar: {}", path.as_ref().display()))?;
+    let raw: RawCalendar = serde_json::from_reader(BufReader::new(file))
+        .with_context(|| format!("Failed to parse rollover calendar: {}", path.as_ref().display()))?;
+
+    let windows = raw
+        .contracts
+        .iter()
+        .map(|e| {
+            Ok(RolloverWindow {
+                name: e.name.clone(),
+                start_ts: parse_et_millis(&e.start)?,
+                end_ts: parse_et_millis(&e.end)?,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    RolloverCalendar::new(windows, raw.max_gap_minutes.unwrap_or(DEFAULT_MAX_GAP_MINUTES))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_calendar() {
+        let cal = load_rollover_calendar("rollover.json").unwrap();
+        assert_eq!(cal.windows.len(), 4);
+        let first = cal.get("2022-11-11_2022-12-20_CLF3_ohlcv1m").unwrap();
+        assert_eq!((first.start_ts, first.end_ts), (1668466800000, 1671055140000));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rollover.json");
+        cal.write_json(&path).unwrap();
+        assert_eq!(load_rollover_calendar(&path).unwrap().windows, cal.windows);
+        assert_eq!("rules:2".parse::<RollMode>().unwrap(), RollMode::Rules { sessions_before_expiry: 2 });
+    }
+
+    #[test]
+    fn test_overlap_rejected() {
+        let windows = vec![
+            RolloverWindow { name: "A".into(), start_ts: 0, end_ts: 10 },
+            RolloverWindow { name: "B".into(), start_ts: 5, end_ts: 20 },
+        ];
+        assert!(RolloverCalendar::new(windows, 1).is_err());
+    }
+}
@@ -0,0 +1,133 @@
+// Derive CL front-month rollover windows from the exchange expiry rules
+//
+// NYMEX CL: trading terminates 3 business days before the 25th calendar day of
+// the month prior to the contract month (4 business days if the 25th is not a
+// business day). We roll a configurable number of sessions before that.
+
+use anyhow::{bail, Context, Result};
+use chrono::{Datelike, Duration, NaiveDate, TimeZone, Weekday};
+use chrono_tz::America::New_York;
+
+use crate::rollover::{RolloverCalendar, RolloverWindow, DEFAULT_MAX_GAP_MINUTES};
+
+const MONTH_CODES: [char; 12] = ['F', 'G', 'H', 'J', 'K', 'M', 'N', 'Q', 'U', 'V', 'X', 'Z'];
+
+/// How far ahead of expiry to roll, and which dates the exchange is closed
+#[derive(Debug, Clone)]
+pub struct RollRule {
+    pub sessions_before_expiry: u32,
+    pub holidays: Vec<NaiveDate>,
+}
+
+impl Default for RollRule {
+    fn default() -> Self {
+        Self { sessions_before_expiry: 3, holidays: Vec::new() }
+    }
+}
+
+impl RollRule {
+    fn is_business_day(&self, date: NaiveDate) -> bool {
+        !matches!(date.weekday(), Weekday::Sat | Weekday::Sun) && !self.holidays.contains(&date)
+    }
+
+    /// Step back `n` business days from `date` (exclusive)
+    fn business_days_before(&self, date: NaiveDate, n: u32) -> NaiveDate {
+        let mut d = date;
+        let mut left = n;
+        while left > 0 {
+            d -= Duration::days(1);
+            if self.is_business_day(d) {
+                left -= 1;
+            }
+        }
+        d
+    }
+}
+
+/// Last trading day of the CL contract for the given delivery month
+pub fn cl_last_trade_date(year: i32, month: u32, rule: &RollRule) -> NaiveDate {
+    let (py, pm) = if month == 1 { (year - 1, 12) } else { (year, month - 1) };
+    let the_25th = NaiveDate::from_ymd_opt(py, pm, 25).unwrap();
+    let n = if rule.is_business_day(the_25th) { 3 } else { 4 };
+    rule.business_days_before(the_25th, n)
+}
+
+/// First trading day on which the next contract becomes the front month
+pub fn roll_date(year: i32, month: u32, rule: &RollRule) -> NaiveDate {
+    let expiry = cl_last_trade_date(year, month, rule);
+    rule.business_days_before(expiry, rule.sessions_before_expiry)
+}
+
+/// Roll instant in UTC epoch millis: the Globex open (18:00 ET) of the session trading on `date`
+pub fn session_open_millis(date: NaiveDate) -> i64 {
+    let eve = date - Duration::days(1);
+    New_York
+        .with_ymd_and_hms(eve.year(), eve.month(), eve.day(), 18, 0, 0)
+        .unwrap()
+        .timestamp_millis()
+}
+
+/// Extract the contract (year, month) from a file stem such as `2022-11-11_2022-12-20_CLF3_ohlcv1m`
+///
+/// The single-digit contract year is resolved against the leading date of the stem.
+pub fn contract_month_from_stem(stem: &str) -> Option<(i32, u32)> {
+    let ref_year: i32 = stem.get(..4)?.parse().ok()?;
+    let pos = stem.find("CL")?;
+    let mut chars = stem[pos + 2..].chars();
+    let code = chars.next()?;
+    let month = MONTH_CODES.iter().position(|&c| c == code)? as u32 + 1;
+    let digit = chars.next()?.to_digit(10)? as i32;
+    let mut year = ref_year - ref_year % 10 + digit;
+    if year < ref_year {
+        year += 10;
+    }
+    Some((year, month))
+}
+
+/// Build a rollover calendar for the given contract file stems
+///
+/// Each contract is front month from the roll out of the previous calendar month's
+/// contract until its own roll.
+pub fn generate_calendar(stems: &[&str], rule: &RollRule) -> Result<RolloverCalendar> {
+    let mut windows = Vec::with_capacity(stems.len());
+    for stem in stems {
+        let (year, month) = contract_month_from_stem(stem)
+            .with_context(|| format!("Cannot derive CL contract month from '{stem}'"))?;
+        let (py, pm) = if month == 1 { (year - 1, 12) } else { (year, month - 1) };
+        let start_ts = session_open_millis(roll_date(py, pm, rule));
+        let end_ts = session_open_millis(roll_date(year, month, rule));
+        if start_ts >= end_ts {
+            bail!("Roll offset of {} sessions leaves no window for {stem}", rule.sessions_before_expiry);
+        }
+        windows.push(RolloverWindow { name: (*stem).to_string(), start_ts, end_ts });
+    }
+    RolloverCalendar::new(windows, DEFAULT_MAX_GAP_MINUTES)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ymd(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn test_last_trade_dates() {
+        let rule = RollRule { sessions_before_expiry: 3, holidays: vec![ymd(2023, 2, 20)] };
+        assert_eq!(cl_last_trade_date(2023, 1, &rule), ymd(2022, 12, 20));
+        assert_eq!(cl_last_trade_date(2023, 2, &rule), ymd(2023, 1, 20));
+        assert_eq!(cl_last_trade_date(2023, 3, &rule), ymd(2023, 2, 21));
+        assert_eq!(cl_last_trade_date(2023, 4, &rule), ymd(2023, 3, 21));
+    }
+
+    #[test]
+    fn test_generate_calendar() {
+        let stems = ["2022-11-11_2022-12-20_CLF3_ohlcv1m", "2022-12-11_2023-01-20_CLG3_ohlcv1m"];
+        let cal = generate_calendar(&stems, &RollRule::default()).unwrap();
+        assert_eq!(contract_month_from_stem(stems[0]), Some((2023, 1)));
+        // CLF3 expires Dec 20 2022; three sessions earlier is Dec 15, which opens Dec 14 18:00 ET
+        assert_eq!(cal.windows[0].end_ts, 1671058800000);
+        assert_eq!(cal.windows[1].start_ts, cal.windows[0].end_ts);
+    }
+}
@@ -0,0 +1,134 @@
+// Split multi-instrument downloads into one bar series per instrument
+//
+// Databento batch files can mix outrights, calendar spreads (CLF3-CLG3) and
+// options in a single file. Bars are partitioned by `instrument_id`, and a
+// `SymbolFilter` decides which instruments are kept.
+
+use std::collections::BTreeMap;
+
+use crate::loader::Bar;
+
+/// Which instruments to keep when splitting
+#[derive(Debug, Clone, Default)]
+pub struct SymbolFilter {
+    pub patterns: Vec<String>, // glob patterns (`*`, `?`); empty keeps every symbol
+    pub include_spreads: bool,
+    pub include_options: bool,
+}
+
+impl SymbolFilter {
+    pub fn accepts(&self, symbol: &str) -> bool {
+        if is_spread(symbol) && !self.include_spreads {
+            return false;
+        }
+        if is_option(symbol) && !self.include_options {
+            return false;
+        }
+        self.patterns.is_empty() || self.patterns.iter().any(|p| glob_match(p, symbol))
+    }
+}
+
+/// Calendar / inter-commodity spreads: `CLF3-CLG3`, or exchange-defined `UD:...` strategies
+pub fn is_spread(symbol: &str) -> bool {
+    symbol.contains('-') || symbol.contains(':')
+}
+
+/// Options carry a strike after a space, e.g. `LOF3 C8500`
+pub fn is_option(symbol: &str) -> bool {
+    symbol.trim().contains(' ')
+}
+
+/// Minimal glob matching supporting `*` (any run) and `?` (any single character)
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let (p, t): (Vec<char>, Vec<char>) = (pattern.chars().collect(), text.chars().collect());
+    let (mut pi, mut ti) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ti));
+            pi += 1;
+        } else if let Some((sp, st)) = star {
+            pi = sp + 1;
+            ti = st + 1;
+            star = Some((sp, st + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+/// One instrument's bars, in input order
+#[derive(Debug, Clone)]
+pub struct InstrumentBars {
+    pub instrument_id: u32,
+    pub symbol: String,
+    pub bars: Vec<Bar>,
+}
+
+impl InstrumentBars {
+    /// Path-safe output name: the sanitised symbol plus the instrument id, so instruments
+    /// sharing a symbol never overwrite each other, e.g. `LOF3_C8500_42005`
+    pub fn file_stem(&self) -> String {
+        format!("{}_{}", sanitize_symbol(&self.symbol), self.instrument_id)
+    }
+}
+
+/// Replace everything but ASCII letters, digits and `-` with `_`
+pub fn sanitize_symbol(symbol: &str) -> String {
+    symbol
+        .trim()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .collect()
+}
+
+/// Partition bars by `instrument_id`, keeping only instruments accepted by `filter`
+///
+/// Instruments are returned in id order; the symbol is taken from the first bar seen.
+pub fn split_by_instrument(bars: Vec<Bar>, filter: &SymbolFilter) -> Vec<InstrumentBars> {
+    let mut groups: BTreeMap<u32, InstrumentBars> = BTreeMap::new();
+    for bar in bars {
+        if !filter.accepts(&bar.instrument_name) {
+            continue;
+        }
+        groups
+            .entry(bar.instrument_id)
+            .or_insert_with(|| InstrumentBars {
+                instrument_id: bar.instrument_id,
+                symbol: bar.instrument_name.clone(),
+                bars: Vec::new(),
+            })
+            .bars
+            .push(bar);
+    }
+    groups.into_values().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bar(id: u32, name: &str) -> Bar {
+        Bar { instrument_name: name.into(), instrument_id: id, ts_event: 0, open: 1, high: 1, low: 1, close: 1, volume: 1 }
+    }
+
+    #[test]
+    fn test_split_and_filter() {
+        let bars = vec![bar(2, "CLG3"), bar(1, "CLF3"), bar(3, "CLF3-CLG3"), bar(4, "LOF3 C8500"), bar(1, "CLF3"), bar(5, "NGF3")];
+        let filter = SymbolFilter { patterns: vec!["CL*".into()], ..Default::default() };
+        let split = split_by_instrument(bars.clone(), &filter);
+        let symbols: Vec<_> = split.iter().map(|i| (i.symbol.as_str(), i.bars.len())).collect();
+        assert_eq!(symbols, vec![("CLF3", 2), ("CLG3", 1)]);
+
+        let all = SymbolFilter { include_spreads: true, include_options: true, ..Default::default() };
+        assert_eq!(split_by_instrument(bars, &all).len(), 5);
+        assert!(glob_match("CL?3", "CLH3") && !glob_match("CL?3", "CLH4"));
+
+        let option = InstrumentBars { instrument_id: 4, symbol: "UD:1V: LO/C8500".into(), bars: Vec::new() };
+        assert_eq!(option.file_stem(), "UD_1V__LO_C8500_4");
+    }
+}
@@ -1,5 +1,13 @@
 use polars::prelude::*;
-use anyhow::Result;
+use anyhow::{bail, ensure, Context, Result};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, File};
+use std::io::BufWriter;
+use std::path::Path;
+use std::str::FromStr;
+use crate::indicators::session_start;
+use crate::timestamps::{millis_lit, time_column, timestamp_millis};
 /// Represents a contract and its valid time window
 #[derive(Debug, Clone)]
 pub struct ContractWindow {
@@ -9,19 +17,25 @@ pub struct ContractWindow {
     pub end_ts: i64,   // exclusive
 }
 
+/// A roll chosen where the back month's session volume overtook the front month's
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RollPoint {
+    pub from: String,
+    pub to: String,
+    pub roll_ts: i64,      // session start (UNIX epoch millis) of the first session on the back month
+    pub front_volume: f64, // front month volume in that session
+    pub back_volume: f64,  // back month volume in that session
+}
+
 /// Truncate contract to its assigned time window
 pub fn truncate_contract(contract: &ContractWindow) -> Result<DataFrame> {
-    let time_col = if contract.df.get_column_names().iter().any(|c| *c == "timestamp") {
-        "timestamp"
-    } else {
-        "ts"
-    };
+    let time_col = time_column(&contract.df);
     let df = contract.df.clone().lazy();
     let filtered = df
         .filter(
             col(time_col)
-                .gt_eq(lit(contract.start_ts))
-                .and(col(time_col).lt(lit(contract.end_ts))),
+                .gt_eq(millis_lit(&contract.df, contract.start_ts))
+                .and(col(time_col).lt(millis_lit(&contract.df, contract.end_ts))),
         )
         .with_column(lit(contract.name.clone()).alias("contract"))
         .collect()?;
@@ -29,14 +43,46 @@ pub fn truncate_contract(contract: &ContractWindow) -> Result<DataFrame> {
     Ok(filtered)
 }
 
-/// Merge all contracts together, preserving order
+/// How prices before each roll are shifted to remove the roll gap
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Adjustment {
+    #[default]
+    None,
+    Difference, // panama: add the incoming - outgoing close
+    Ratio,      // multiply by incoming / outgoing close
+}
+
+impl FromStr for Adjustment {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(Self::None),
+            "difference" => Ok(Self::Difference),
+            "ratio" => Ok(Self::Ratio),
+            other => bail!("Unknown adjustment '{other}' (expected none, difference or ratio)"),
+        }
+    }
+}
+
+const PRICE_COLUMNS: [&str; 4] = ["open", "high", "low", "close"];
+
+/// Merge all contracts together in window order
+///
+/// Windows are given oldest first (as the rollover calendar yields them), so the
+/// output is chronological.
 pub fn stitch_contracts(windows: &[ContractWindow]) -> Result<DataFrame> {
-    let mut stitched = windows
+    let stitched = windows
         .iter()
         .map(truncate_contract)
         .collect::<Result<Vec<_>>>()?;
-    if let Some(mut out) = stitched.pop() {
-        while let Some(df) = stitched.pop() {
+    vstack_all(stitched)
+}
+
+fn vstack_all(frames: Vec<DataFrame>) -> Result<DataFrame> {
+    let mut frames = frames.into_iter();
+    if let Some(mut out) = frames.next() {
+        for df in frames {
             out.vstack_mut(&df)?;
         }
         Ok(out)
@@ -45,6 +91,169 @@ pub fn stitch_contracts(windows: &[ContractWindow]) -> Result<DataFrame> {
     }
 }
 
+fn close_by_timestamp(df: &DataFrame) -> Result<Vec<(i64, f64)>> {
+    let ts = timestamp_millis(df)?;
+    let close = df.column("close")?.cast(&DataType::Float64)?;
+    Ok(ts
+        .into_iter()
+        .zip(close.f64()?)
+        .filter_map(|(t, c)| Some((t?, c?)))
+        .collect())
+}
+
+/// Closes `(outgoing, incoming)` of two adjacent contracts on their last common bar before the roll
+pub fn roll_closes(outgoing: &ContractWindow, incoming: &ContractWindow) -> Result<(f64, f64)> {
+    let incoming_close: BTreeMap<i64, f64> = close_by_timestamp(&incoming.df)?.into_iter().collect();
+    let mut outgoing_close = close_by_timestamp(&outgoing.df)?;
+    outgoing_close.sort_by_key(|(t, _)| *t);
+    let common = outgoing_close
+        .iter()
+        .rev()
+        .filter(|(t, _)| *t < incoming.start_ts)
+        .find_map(|(t, out)| incoming_close.get(t).map(|inc| (*out, *inc)));
+    match common {
+        Some(closes) => Ok(closes),
+        None => bail!("No overlapping bars between {} and {} before the roll", outgoing.name, incoming.name),
+    }
+}
+
+/// Stitch contracts into a back-adjusted continuous series
+///
+/// OHLC (plus any `extra_columns`, e.g. VWAP or EMA columns) of every contract are
+/// adjusted by the accumulated gaps of all later rolls, so the most recent contract
+/// keeps its traded prices. `range_columns` hold price distances such as ATR: they are
+/// scaled by ratio adjustment and left alone by difference adjustment. The original values are kept as `<column>_unadj`, and an
+/// `adjustment` column records the offset (difference) or factor (ratio) applied.
+/// Roll gaps are `incoming - outgoing` close (difference) or `incoming / outgoing` (ratio).
+pub fn stitch_contracts_adjusted(
+    windows: &[ContractWindow],
+    method: Adjustment,
+    extra_columns: &[&str],
+    range_columns: &[&str],
+) -> Result<DataFrame> {
+    let pairs = windows.windows(2).map(|pair| Ok((pair, roll_closes(&pair[0], &pair[1])?)));
+    let gaps = match method {
+        Adjustment::None => return stitch_contracts(windows),
+        Adjustment::Difference => pairs.map(|r| r.map(|(_, (out, inc))| inc - out)).collect::<Result<Vec<_>>>()?,
+        Adjustment::Ratio => pairs
+            .map(|r| {
+                let (pair, (out, inc)) = r?;
+                let ratio = inc / out;
+                ensure!(
+                    ratio.is_finite() && ratio != 0.0,
+                    "Cannot ratio-adjust the roll from {} to {}: outgoing close {out}, incoming close {inc}",
+                    pair[0].name, pair[1].name
+                );
+                Ok(ratio)
+            })
+            .collect::<Result<Vec<_>>>()?,
+    };
+
+    let mut frames = Vec::with_capacity(windows.len());
+    for (i, window) in windows.iter().enumerate() {
+        let adj = match method {
+            Adjustment::Ratio => gaps[i..].iter().product::<f64>(),
+            _ => gaps[i..].iter().sum::<f64>(),
+        };
+        let df = truncate_contract(window)?;
+        let names = df.get_column_names_owned();
+        let scaled: &[&str] = if method == Adjustment::Ratio { range_columns } else { &[] };
+        let mut exprs = Vec::new();
+        for name in PRICE_COLUMNS.iter().chain(extra_columns).chain(scaled) {
+            if !names.iter().any(|c| c.as_str() == *name) {
+                continue;
+            }
+            let raw = col(*name).cast(DataType::Float64);
+            let adjusted = match method {
+                Adjustment::Ratio => raw.clone() * lit(adj),
+                _ => raw.clone() + lit(adj), // never a range column
+            };
+            exprs.push(raw.alias(format!("{name}_unadj")));
+            exprs.push(adjusted.alias(*name));
+        }
+        exprs.push(lit(adj).alias("adjustment"));
+        frames.push(df.lazy().with_columns(exprs).collect()?);
+    }
+    vstack_all(frames)
+}
+
+/// Total volume per trading session (18:00 ET start), keyed by session start millis
+fn session_volumes(df: &DataFrame) -> Result<BTreeMap<i64, f64>> {
+    let ts = timestamp_millis(df)?;
+    let volume = df.column("volume")?.cast(&DataType::Float64)?;
+    let mut out = BTreeMap::new();
+    for (t, v) in ts.into_iter().zip(volume.f64()?) {
+        if let (Some(t), Some(v)) = (t, v) {
+            *out.entry(session_start(t)).or_insert(0.0) += v;
+        }
+    }
+    Ok(out)
+}
+
+/// Derive rollover windows from overlapping contracts by volume crossover
+///
+/// `contracts` must be in expiry order. Each roll happens at the start of the first
+/// session in which the next contract trades more volume than the current one; if
+/// that never happens, the roll falls on the first session after the front month's
+/// data ends. The first window is open at the start and the last at the end.
+pub fn volume_crossover_windows(contracts: Vec<(String, DataFrame)>) -> Result<(Vec<ContractWindow>, Vec<RollPoint>)> {
+    let volumes = contracts
+        .iter()
+        .map(|(_, df)| session_volumes(df))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut rolls: Vec<RollPoint> = Vec::with_capacity(contracts.len().saturating_sub(1));
+    for i in 1..contracts.len() {
+        let (front, back) = (&volumes[i - 1], &volumes[i]);
+        let earliest = rolls.last().map_or(i64::MIN, |r| r.roll_ts + 1);
+        let crossover = back
+            .range(earliest..)
+            .find_map(|(&sess, &bv)| front.get(&sess).filter(|&&fv| bv > fv).map(|&fv| (sess, fv, bv)));
+        let (roll_ts, front_volume, back_volume) = match crossover {
+            Some(hit) => hit,
+            None => {
+                let last_front = front.keys().next_back().copied().unwrap_or(i64::MIN);
+                match back.range(earliest.max(last_front.saturating_add(1))..).next() {
+                    Some((&sess, &bv)) => (sess, 0.0, bv),
+                    None => bail!("No roll point found between {} and {}", contracts[i - 1].0, contracts[i].0),
+                }
+            }
+        };
+        rolls.push(RollPoint {
+            from: contracts[i - 1].0.clone(),
+            to: contracts[i].0.clone(),
+            roll_ts,
+            front_volume,
+            back_volume,
+        });
+    }
+
+    let windows = contracts
+        .into_iter()
+        .enumerate()
+        .map(|(i, (name, df))| ContractWindow {
+            name,
+            df,
+            start_ts: if i == 0 { i64::MIN } else { rolls[i - 1].roll_ts },
+            end_ts: rolls.get(i).map_or(i64::MAX, |r| r.roll_ts),
+        })
+        .collect();
+
+    Ok((windows, rolls))
+}
+
+/// Save the roll points chosen by [`volume_crossover_windows`] as JSON
+pub fn write_roll_points<P: AsRef<Path>>(rolls: &[RollPoint], path: P) -> Result<()> {
+    let path = path.as_ref();
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent)?;
+    }
+    let file = File::create(path)
+        .with_context(|| format!("Failed to create roll report: {}", path.display()))?;
+    serde_json::to_writer_pretty(BufWriter::new(file), rolls)?;
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -74,7 +283,90 @@ mod tests {
         ];
         let stitched = stitch_contracts(&windows).unwrap();
         assert_eq!(stitched.height(), 6);
-        assert!(stitched.column("contract").is_ok());
+        let contracts: Vec<_> = stitched.column("contract").unwrap().str().unwrap().into_no_null_iter().collect();
+        assert_eq!(contracts, vec!["A", "A", "A", "B", "B", "B"]); // chronological, oldest window first
+    }
+
+    #[test]
+    fn test_volume_crossover() {
+        // Three sessions, one bar each at 19:00 ET (the session starting 18:00 ET that day)
+        let day = 86_400_000i64;
+        let ts = [1668470400000i64, 1668470400000 + day, 1668470400000 + 2 * day];
+        let front = df!(
+            "timestamp" => ts,
+            "close" => [1.0, 1.0, 1.0],
+            "volume" => [100.0, 80.0, 10.0]
+        ).unwrap();
+        let back = df!(
+            "timestamp" => ts,
+            "close" => [2.0, 2.0, 2.0],
+            "volume" => [10.0, 90.0, 200.0]
+        ).unwrap();
+        let (windows, rolls) = volume_crossover_windows(vec![("F".into(), front), ("G".into(), back)]).unwrap();
+        assert_eq!(rolls.len(), 1);
+        assert_eq!(rolls[0].roll_ts, session_start(ts[1]));
+        assert_eq!((rolls[0].front_volume, rolls[0].back_volume), (80.0, 90.0));
+        assert_eq!(windows[0].end_ts, windows[1].start_ts);
+        let stitched = stitch_contracts(&windows).unwrap();
+        assert_eq!(stitched.height(), 3);
+
+        let dir = tempfile::tempdir().unwrap();
+        write_roll_points(&rolls, dir.path().join("rolls.json")).unwrap();
+        let report: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(dir.path().join("rolls.json")).unwrap()).unwrap();
+        assert_eq!((report[0]["from"].as_str(), report[0]["roll_ts"].as_i64()), (Some("F"), Some(rolls[0].roll_ts)));
+    }
+
+    #[test]
+    fn test_back_adjustment() {
+        let df1 = df!(
+            "timestamp" => [1i64,2,3,4],
+            "open" => [10.0,10.0,10.0,10.0],
+            "high" => [10.0,10.0,10.0,10.0],
+            "low" => [10.0,10.0,10.0,10.0],
+            "close" => [10.0,10.0,10.0,10.0],
+            "atr" => [1.0,1.0,1.0,1.0]
+        ).unwrap();
+        let df2 = df!(
+            "timestamp" => [2i64,3,4,5],
+            "open" => [12.0,12.0,12.0,12.0],
+            "high" => [12.0,12.0,12.0,12.0],
+            "low" => [12.0,12.0,12.0,12.0],
+            "close" => [12.0,12.0,12.0,12.0],
+            "atr" => [1.0,1.0,1.0,1.0]
+        ).unwrap();
+        let windows = vec![
+            ContractWindow { name: "A".into(), df: df1, start_ts: 1, end_ts: 4 },
+            ContractWindow { name: "B".into(), df: df2, start_ts: 4, end_ts: 6 },
+        ];
+        let diff = stitch_contracts_adjusted(&windows, Adjustment::Difference, &[], &["atr"]).unwrap();
+        let close = diff.column("close").unwrap().f64().unwrap();
+        assert_eq!(close.get(0), Some(12.0));
+        assert_eq!(diff.column("close_unadj").unwrap().f64().unwrap().get(0), Some(10.0));
+        assert_eq!(diff.column("adjustment").unwrap().f64().unwrap().get(0), Some(2.0));
+        assert_eq!(diff.column("atr").unwrap().f64().unwrap().get(0), Some(1.0)); // a range is shift-invariant
+        let ratio = stitch_contracts_adjusted(&windows, Adjustment::Ratio, &[], &["atr"]).unwrap();
+        assert_eq!(ratio.column("adjustment").unwrap().f64().unwrap().get(0), Some(1.2));
+        assert_eq!(ratio.column("close").unwrap().f64().unwrap().get(4), Some(12.0));
+        assert_eq!(ratio.column("atr").unwrap().f64().unwrap().get(0), Some(1.2));
+        assert_eq!(ratio.column("atr_unadj").unwrap().f64().unwrap().get(0), Some(1.0));
+
+        let mut zero = windows.clone();
+        zero[0].df.with_column(Column::new("close".into(), [0.0; 4])).unwrap();
+        let err = stitch_contracts_adjusted(&zero, Adjustment::Ratio, &[], &[]).unwrap_err();
+        assert!(err.to_string().contains("from A to B"), "{err}");
     }
-}
 
+    #[test]
+    fn test_truncate_typed_timestamp() {
+        let mut df = df!(
+            "timestamp" => [1_000i64, 2_000, 3_000],
+            "close" => [1.0, 2.0, 3.0]
+        ).unwrap();
+        crate::timestamps::normalize_timestamp(&mut df).unwrap();
+        let window = ContractWindow { name: "A".into(), df, start_ts: 2_000, end_ts: 3_000 };
+        let out = truncate_contract(&window).unwrap();
+        assert_eq!(out.column("close").unwrap().f64().unwrap().get(0), Some(2.0));
+        assert_eq!(out.height(), 1);
+        assert_eq!(out.column("timestamp").unwrap().dtype(), &crate::timestamps::timestamp_dtype());
+    }
+}
@@ -6,22 +6,91 @@
 
 
 use polars::prelude::*;
-use polars::io::parquet::{read::ParquetReader, write::ParquetWriter};
+use polars::io::parquet::{read::ParquetReader, write::{KeyValueMetadata, ParquetWriter}};
+use std::collections::BTreeMap;
 use std::fs::{File, create_dir_all};
+use std::io::Write;
 use std::path::Path;
-use anyhow::{Result, Context};
+use tempfile::NamedTempFile;
+use anyhow::{ensure, Result, Context};
 /// Save a DataFrame to a Parquet file
 pub fn write_parquet(df: &DataFrame, path: &str) -> Result<()> {
-    if let Some(parent) = Path::new(path).parent() {
-        create_dir_all(parent)
-            .with_context(|| format!("Failed to create parent directory for {path}"))?;
+    write_parquet_with_metadata(df, path, &[])
+}
+
+/// Save a DataFrame to a Parquet file with extra file-level key/value metadata
+///
+/// The data goes to a temp file in the same directory, which is fsynced, checked by
+/// reading back its footer and only then renamed over `path`. A crash mid-write
+/// leaves the previous file (or none) in place, never a truncated one.
+pub fn write_parquet_with_metadata(df: &DataFrame, path: &str, metadata: &[(String, String)]) -> Result<()> {
+    write_atomic(path, |file| write_to(df, file, metadata), |tmp| {
+        verify_footer(tmp, df.height()).with_context(|| format!("Parquet written for {path} failed verification"))
+    })
+}
+
+/// Replace `path` with `bytes` the same way, so readers never see a partial file
+pub fn write_bytes_atomic(path: &str, bytes: &[u8]) -> Result<()> {
+    write_atomic(path, |file| Ok(file.write_all(bytes)?), |_| Ok(()))
+}
+
+/// Run `write` against a temp file next to `path`, sync and `verify` it, then move it into place
+fn write_atomic(
+    path: &str,
+    write: impl FnOnce(&mut File) -> Result<()>,
+    verify: impl FnOnce(&Path) -> Result<()>,
+) -> Result<()> {
+    let dir = create_parent(path)?;
+    let mut tmp = temp_file_in(dir)
+        .with_context(|| format!("Failed to create temp file for {path}"))?;
+    // Keep the mode of the file being replaced
+    if let Ok(existing) = std::fs::metadata(path) {
+        tmp.as_file().set_permissions(existing.permissions())
+            .with_context(|| format!("Failed to copy permissions of {path}"))?;
+    }
+    write(tmp.as_file_mut())?;
+    tmp.as_file().sync_all()
+        .with_context(|| format!("Failed to sync temp file for {path}"))?;
+    verify(tmp.path())?;
+    tmp.persist(path)
+        .with_context(|| format!("Failed to move temp file into place: {path}"))?;
+    // Persist the rename itself; not every platform can sync a directory handle
+    if let Ok(dir) = File::open(dir) {
+        let _ = dir.sync_all();
     }
-    let file = File::create(path)
-        .with_context(|| format!("Failed to create output file: {path}"))?;
+    Ok(())
+}
 
+/// Temp file created with the mode `File::create` would give (0666 less the umask)
+/// instead of tempfile's owner-only 0600
+fn temp_file_in(dir: &Path) -> std::io::Result<NamedTempFile> {
+    let mut builder = tempfile::Builder::new();
+    #[cfg(unix)]
+    builder.permissions(std::os::unix::fs::PermissionsExt::from_mode(0o666));
+    builder.tempfile_in(dir)
+}
+
+/// Read back a Parquet footer and check its row count
+fn verify_footer(path: &Path, rows: usize) -> Result<()> {
+    let mut reader = ParquetReader::new(File::open(path)?);
+    let metadata = reader.get_metadata().context("Unreadable Parquet footer")?;
+    ensure!(metadata.num_rows == rows, "Footer records {} rows, expected {rows}", metadata.num_rows);
+    Ok(())
+}
+
+/// Create the parent directory of `path`, returning it
+fn create_parent(path: &str) -> Result<&Path> {
+    let parent = Path::new(path).parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    create_dir_all(parent)
+        .with_context(|| format!("Failed to create parent directory for {path}"))?;
+    Ok(parent)
+}
+
+fn write_to<W: Write>(df: &DataFrame, writer: W, metadata: &[(String, String)]) -> Result<()> {
     let mut df = df.clone();
-    ParquetWriter::new(file)
+    ParquetWriter::new(writer)
         .with_compression(ParquetCompression::Zstd(None))
+        .with_key_value_metadata((!metadata.is_empty()).then(|| KeyValueMetadata::from_static(metadata.to_vec())))
         .finish(&mut df)
         .context("Failed to write Parquet data")?;
 
@@ -40,11 +109,25 @@ pub fn read_parquet(path: &str) -> Result<DataFrame> {
     Ok(df)
 }
 
+/// File-level key/value metadata of a Parquet file
+pub fn read_parquet_metadata(path: &str) -> Result<BTreeMap<String, String>> {
+    let file = File::open(path)
+        .with_context(|| format!("Failed to open Parquet file: {path}"))?;
+
+    let mut reader = ParquetReader::new(file);
+    let metadata = reader.get_metadata().context("Failed to read Parquet metadata")?;
+    Ok(metadata
+        .key_value_metadata
+        .iter()
+        .flatten()
+        .filter_map(|kv| Some((kv.key.clone(), kv.value.clone()?)))
+        .collect())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use polars::df;
-    use tempfile::NamedTempFile;
 
     #[test]
     fn test_parquet_roundtrip() {
@@ -61,4 +144,53 @@ mod tests {
         let read = read_parquet(file.path().to_str().unwrap()).unwrap();
         assert_eq!(read.height(), df.height());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_key_value_metadata() {
+        let df = df!("close" => [1.0]).unwrap();
+        let file = NamedTempFile::new().unwrap();
+        let path = file.path().to_str().unwrap();
+        write_parquet_with_metadata(&df, path, &[("price_format".into(), "fixed".into())]).unwrap();
+        assert_eq!(read_parquet_metadata(path).unwrap().get("price_format").map(String::as_str), Some("fixed"));
+    }
+
+    #[test]
+    fn test_interrupted_write_keeps_old_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.parquet");
+        let path = path.to_str().unwrap();
+        write_parquet(&df!("close" => [1.0, 2.0]).unwrap(), path).unwrap();
+
+        // Crash halfway through the data, then a write that "succeeds" with a truncated file
+        let check = |tmp: &Path| verify_footer(tmp, 1);
+        let crashed = write_atomic(path, |file| {
+            file.write_all(b"PAR1 partial row group")?;
+            anyhow::bail!("simulated crash")
+        }, check);
+        assert!(crashed.is_err());
+        let truncated = write_atomic(path, |file| Ok(file.write_all(b"PAR1 partial row group")?), check);
+        assert!(truncated.is_err());
+
+        assert_eq!(read_parquet(path).unwrap().height(), 2);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1); // no temp file left behind
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_output_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = |p: &Path| std::fs::metadata(p).unwrap().permissions().mode() & 0o777;
+        let dir = tempfile::tempdir().unwrap();
+        let plain = dir.path().join("plain");
+        File::create(&plain).unwrap();
+        let path = dir.path().join("a.parquet");
+        let df = df!("close" => [1.0]).unwrap();
+
+        // New files get the umask default, replaced files keep their mode
+        write_parquet(&df, path.to_str().unwrap()).unwrap();
+        assert_eq!(mode(&path), mode(&plain));
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o640)).unwrap();
+        write_parquet(&df, path.to_str().unwrap()).unwrap();
+        assert_eq!(mode(&path), 0o640);
+    }
+}
@@ -0,0 +1,112 @@
+// Typed timestamp column shared by every stage of the pipeline
+//
+// Bars carry a `timestamp` column of `Datetime(ms, "UTC")`. Older frames (and
+// hand-built test frames) may still hold naive Int64 millis or another Datetime
+// unit, so readers go through `timestamp_millis` rather than casting blindly.
+
+use anyhow::{bail, Result};
+use polars::prelude::*;
+
+pub const TIMESTAMP_COL: &str = "timestamp";
+pub const LOCAL_TIME_COL: &str = "timestamp_et";
+pub const LOCAL_TZ: &str = "America/New_York";
+
+/// `Datetime(ms, "UTC")`, the dtype of the `timestamp` column
+pub fn timestamp_dtype() -> DataType {
+    DataType::Datetime(TimeUnit::Milliseconds, Some(TimeZone::UTC))
+}
+
+/// Name of the time column: `timestamp`, or `ts` for older frames
+pub fn time_column(df: &DataFrame) -> &'static str {
+    if df.get_column_names().iter().any(|c| *c == TIMESTAMP_COL) {
+        TIMESTAMP_COL
+    } else {
+        "ts"
+    }
+}
+
+fn millis_per_unit(unit: TimeUnit) -> (i64, i64) {
+    // (multiplier, divisor) converting a value in `unit` to millis
+    match unit {
+        TimeUnit::Milliseconds => (1, 1),
+        TimeUnit::Microseconds => (1, 1_000),
+        TimeUnit::Nanoseconds => (1, 1_000_000),
+    }
+}
+
+/// Time column as UTC epoch millis, whether stored as Int64 millis or any Datetime unit
+pub fn timestamp_millis(df: &DataFrame) -> Result<Int64Chunked> {
+    let column = df.column(time_column(df))?;
+    let (mul, div) = match column.dtype() {
+        DataType::Datetime(unit, _) => millis_per_unit(*unit),
+        DataType::Int64 => (1, 1),
+        other => bail!("Unsupported timestamp dtype {other:?}"),
+    };
+    let physical = column.as_materialized_series().to_physical_repr().into_owned();
+    let values = physical.i64()?;
+    Ok(if (mul, div) == (1, 1) {
+        values.clone()
+    } else {
+        values.apply_values(|v| v.div_euclid(div) * mul)
+    })
+}
+
+/// Convert epoch millis to a literal comparable with the frame's time column
+pub fn millis_lit(df: &DataFrame, ms: i64) -> Expr {
+    match df.column(time_column(df)).map(|c| c.dtype().clone()) {
+        Ok(DataType::Datetime(unit, tz)) => {
+            let value = match unit {
+                TimeUnit::Milliseconds => ms,
+                TimeUnit::Microseconds => ms.saturating_mul(1_000),
+                TimeUnit::Nanoseconds => ms.saturating_mul(1_000_000),
+            };
+            lit(value).cast(DataType::Datetime(unit, tz))
+        }
+        _ => lit(ms),
+    }
+}
+
+/// Series of UTC epoch millis typed as the `timestamp` column
+pub fn millis_to_timestamp(name: &str, millis: Int64Chunked) -> Result<Series> {
+    Ok(millis
+        .with_name(name.into())
+        .into_datetime(TimeUnit::Milliseconds, Some(TimeZone::UTC))
+        .into_series())
+}
+
+/// Rewrite the time column as `Datetime(ms, "UTC")`
+pub fn normalize_timestamp(df: &mut DataFrame) -> Result<()> {
+    let name = time_column(df);
+    let ts = millis_to_timestamp(name, timestamp_millis(df)?)?;
+    df.with_column(ts)?;
+    Ok(())
+}
+
+/// Add a `timestamp_et` column: the same instants typed in America/New_York
+pub fn with_local_time(df: &mut DataFrame) -> Result<()> {
+    let tz = TimeZone::opt_try_new(Some(LOCAL_TZ))?;
+    let local = timestamp_millis(df)?
+        .with_name(LOCAL_TIME_COL.into())
+        .into_datetime(TimeUnit::Milliseconds, tz)
+        .into_series();
+    df.with_column(local)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_units_normalize_to_millis() {
+        let mut df = df!("timestamp" => [1_500i64, 2_000]).unwrap();
+        let ns = df.column("timestamp").unwrap().cast(&DataType::Int64).unwrap()
+            .as_materialized_series().clone() * 1_000_000;
+        df.with_column(ns.cast(&DataType::Datetime(TimeUnit::Nanoseconds, None)).unwrap()).unwrap();
+        normalize_timestamp(&mut df).unwrap();
+        assert_eq!(df.column("timestamp").unwrap().dtype(), &timestamp_dtype());
+        assert_eq!(timestamp_millis(&df).unwrap().into_no_null_iter().collect::<Vec<_>>(), vec![1_500, 2_000]);
+        with_local_time(&mut df).unwrap();
+        assert!(matches!(df.column(LOCAL_TIME_COL).unwrap().dtype(), DataType::Datetime(_, Some(tz)) if tz.as_str() == LOCAL_TZ));
+    }
+}
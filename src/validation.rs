@@ -0,0 +1,350 @@
+// Data quality checks for 1-minute bars
+//
+// Runs on `Vec<Bar>` or on a `bars_to_dataframe`-shaped frame. Each instrument
+// is checked separately for OHLC consistency, zero volume, timestamps off the
+// bar grid, duplicate and out-of-order timestamps, and price spikes larger than
+// N times the recent ATR. Every violation lands in a serializable report; the
+// policy decides whether bad bars are kept, rejected, dropped or repaired.
+
+use anyhow::{bail, Context, Result};
+use polars::prelude::*;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::fs::{create_dir_all, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::loader::Bar;
+use crate::prices::{FIXED_POINT_SCALE, PRICE_COLUMNS};
+use crate::resampler::{INSTRUMENT_ID_COL, NANOS_PER_MILLI};
+use crate::timestamps::{millis_to_timestamp, time_column, timestamp_millis};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ViolationKind {
+    OhlcInconsistent,   // low/high don't bound open and close
+    ZeroVolume,
+    Misaligned,         // timestamp not on the bar interval grid
+    DuplicateTimestamp,
+    OutOfOrder,
+    PriceSpike,         // true range beyond `spike_atrs` x ATR
+}
+
+/// What to do with bars that fail validation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ValidationPolicy {
+    #[default]
+    Report, // keep everything, only report
+    Fail,   // error out if anything was found
+    Drop,   // drop every bar with a violation (first of a duplicate pair is kept)
+    Repair, // fix OHLC bounds, snap to the grid, sort, dedupe; drop zero-volume bars and spikes
+}
+
+impl FromStr for ValidationPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "report" => Ok(Self::Report),
+            "fail" => Ok(Self::Fail),
+            "drop" => Ok(Self::Drop),
+            "repair" => Ok(Self::Repair),
+            other => bail!("Unknown validation policy '{other}' (expected report, fail, drop or repair)"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ValidationConfig {
+    pub policy: ValidationPolicy,
+    pub interval_ns: i64,            // bar grid; timestamps must be multiples of it
+    pub spike_atrs: f64,             // true range above this many ATRs is a spike
+    pub atr_period: usize,           // bars in the trailing ATR
+    pub report_dir: Option<PathBuf>, // pipeline writes `<stem>.validation.json` here
+}
+
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        Self {
+            policy: ValidationPolicy::default(),
+            interval_ns: 60 * 1_000 * NANOS_PER_MILLI,
+            spike_atrs: 10.0,
+            atr_period: 14,
+            report_dir: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Violation {
+    pub row: usize, // index in the input
+    pub instrument_id: u32,
+    pub ts_event: i64,
+    pub kind: ViolationKind,
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ValidationReport {
+    pub rows: usize,
+    pub kept: usize,
+    pub counts: BTreeMap<ViolationKind, usize>,
+    pub violations: Vec<Violation>,
+}
+
+impl ValidationReport {
+    pub fn is_clean(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    /// Error out under `ValidationPolicy::Fail` if anything was found
+    pub fn enforce(&self, policy: ValidationPolicy) -> Result<()> {
+        if policy == ValidationPolicy::Fail && !self.is_clean() {
+            bail!("Bar validation failed with {} violations: {:?}", self.violations.len(), self.counts);
+        }
+        Ok(())
+    }
+
+    pub fn write_json<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create validation report: {}", path.display()))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), self)?;
+        Ok(())
+    }
+}
+
+/// Row-level checks that need no neighbours
+fn bar_violations(bar: &Bar, interval_ns: i64) -> Vec<(ViolationKind, String)> {
+    let mut out = Vec::new();
+    if bar.low > bar.high || bar.low > bar.open.min(bar.close) || bar.high < bar.open.max(bar.close) {
+        let detail = format!("o={} h={} l={} c={}", bar.open, bar.high, bar.low, bar.close);
+        out.push((ViolationKind::OhlcInconsistent, detail));
+    }
+    if bar.volume == 0 {
+        out.push((ViolationKind::ZeroVolume, String::new()));
+    }
+    if bar.ts_event.rem_euclid(interval_ns) != 0 {
+        out.push((ViolationKind::Misaligned, format!("{} ns past the grid", bar.ts_event.rem_euclid(interval_ns))));
+    }
+    out
+}
+
+fn repair(bar: &Bar, interval_ns: i64) -> Bar {
+    let mut fixed = bar.clone();
+    fixed.ts_event -= bar.ts_event.rem_euclid(interval_ns);
+    fixed.high = bar.high.max(bar.open).max(bar.close).max(bar.low);
+    fixed.low = bar.low.min(bar.open).min(bar.close).min(bar.high);
+    fixed
+}
+
+/// Check every instrument; returns violations and the `(input row, bar)` pairs to keep
+fn inspect(bars: &[Bar], config: &ValidationConfig) -> (Vec<Violation>, Vec<(usize, Bar)>) {
+    let mut groups: BTreeMap<u32, Vec<usize>> = BTreeMap::new();
+    for (i, bar) in bars.iter().enumerate() {
+        groups.entry(bar.instrument_id).or_default().push(i);
+    }
+
+    let mut violations = Vec::new();
+    let mut kept = Vec::with_capacity(bars.len());
+    for rows in groups.values() {
+        let mut seen = HashSet::new();
+        let mut max_ts = i64::MIN;
+        let mut candidates = Vec::with_capacity(rows.len());
+        for &row in rows {
+            let bar = &bars[row];
+            let mut found = bar_violations(bar, config.interval_ns);
+            if !seen.insert(bar.ts_event) {
+                found.push((ViolationKind::DuplicateTimestamp, String::new()));
+            } else if bar.ts_event < max_ts {
+                found.push((ViolationKind::OutOfOrder, format!("{} ns before the previous bar", max_ts - bar.ts_event)));
+            }
+            max_ts = max_ts.max(bar.ts_event);
+
+            let clean = found.is_empty();
+            let zero_volume = found.iter().any(|(k, _)| *k == ViolationKind::ZeroVolume);
+            violations.extend(found.into_iter().map(|(kind, detail)| Violation {
+                row,
+                instrument_id: bar.instrument_id,
+                ts_event: bar.ts_event,
+                kind,
+                detail,
+            }));
+            match config.policy {
+                ValidationPolicy::Drop if !clean => {}
+                ValidationPolicy::Repair if zero_volume => {}
+                ValidationPolicy::Repair => candidates.push((row, repair(bar, config.interval_ns))),
+                _ => candidates.push((row, bar.clone())),
+            }
+        }
+        if config.policy == ValidationPolicy::Repair {
+            candidates.sort_by_key(|(_, b)| b.ts_event);
+            candidates.dedup_by_key(|(_, b)| b.ts_event);
+        }
+
+        // Spikes: true range against the mean of the last `atr_period` non-spike bars
+        let mut ranges: VecDeque<f64> = VecDeque::with_capacity(config.atr_period + 1);
+        let mut prev_close: Option<i64> = None;
+        for (row, bar) in candidates {
+            let prev = prev_close.unwrap_or(bar.open);
+            let tr = (bar.high - bar.low).max((bar.high - prev).abs()).max((bar.low - prev).abs()) as f64;
+            let atr = ranges.iter().sum::<f64>() / ranges.len().max(1) as f64;
+            let spike = ranges.len() == config.atr_period && atr > 0.0 && tr > config.spike_atrs * atr;
+            if spike {
+                violations.push(Violation {
+                    row,
+                    instrument_id: bar.instrument_id,
+                    ts_event: bar.ts_event,
+                    kind: ViolationKind::PriceSpike,
+                    detail: format!("true range {:.1} ATRs", tr / atr),
+                });
+                if matches!(config.policy, ValidationPolicy::Drop | ValidationPolicy::Repair) {
+                    continue;
+                }
+            } else {
+                ranges.push_back(tr);
+                if ranges.len() > config.atr_period {
+                    ranges.pop_front();
+                }
+                prev_close = Some(bar.close);
+            }
+            kept.push((row, bar));
+        }
+    }
+    violations.sort_by_key(|v| (v.row, v.kind));
+    (violations, kept)
+}
+
+fn report(rows: usize, kept: usize, violations: Vec<Violation>) -> ValidationReport {
+    let mut counts = BTreeMap::new();
+    for v in &violations {
+        *counts.entry(v.kind).or_insert(0) += 1;
+    }
+    ValidationReport { rows, kept, counts, violations }
+}
+
+fn keeps_input(policy: ValidationPolicy) -> bool {
+    matches!(policy, ValidationPolicy::Report | ValidationPolicy::Fail)
+}
+
+/// Validate bars, applying `config.policy`
+///
+/// Under `Drop` and `Repair` the output is grouped by instrument id, each instrument
+/// in time order (`Repair`) or input order (`Drop`). `Fail` returns the input
+/// unchanged; call [`ValidationReport::enforce`] after saving the report.
+pub fn validate_bars(bars: Vec<Bar>, config: &ValidationConfig) -> (Vec<Bar>, ValidationReport) {
+    let (violations, kept) = inspect(&bars, config);
+    let rows = bars.len();
+    let out = if keeps_input(config.policy) { bars } else { kept.into_iter().map(|(_, b)| b).collect() };
+    let kept = out.len();
+    (out, report(rows, kept, violations))
+}
+
+/// Validate a 1-minute frame (`timestamp`, OHLCV, optional `instrument_id`)
+///
+/// Same rules as [`validate_bars`]; other columns of kept rows are carried along.
+pub fn validate_dataframe(df: &DataFrame, config: &ValidationConfig) -> Result<(DataFrame, ValidationReport)> {
+    let ts = timestamp_millis(df)?;
+    let prices = PRICE_COLUMNS
+        .iter()
+        .map(|c| Ok(df.column(c)?.cast(&DataType::Float64)?.f64()?.clone()))
+        .collect::<Result<Vec<_>>>()?;
+    let volume = df.column("volume")?.cast(&DataType::Float64)?;
+    let volume = volume.f64()?;
+    let ids = match df.column(INSTRUMENT_ID_COL) {
+        Ok(c) => Some(c.cast(&DataType::UInt32)?.u32()?.clone()),
+        Err(_) => None,
+    };
+    let raw = |i: usize, row: usize| (prices[i].get(row).unwrap_or(f64::NAN) * FIXED_POINT_SCALE as f64).round() as i64;
+    let bars: Vec<Bar> = (0..df.height())
+        .map(|row| Bar {
+            instrument_name: String::new(),
+            instrument_id: ids.as_ref().and_then(|ids| ids.get(row)).unwrap_or(0),
+            ts_event: ts.get(row).unwrap_or(0) * NANOS_PER_MILLI,
+            open: raw(0, row),
+            high: raw(1, row),
+            low: raw(2, row),
+            close: raw(3, row),
+            volume: volume.get(row).unwrap_or(0.0).round() as u64,
+        })
+        .collect();
+
+    let (violations, kept) = inspect(&bars, config);
+    if keeps_input(config.policy) {
+        return Ok((df.clone(), report(df.height(), df.height(), violations)));
+    }
+
+    let idx = IdxCa::from_vec("idx".into(), kept.iter().map(|(row, _)| *row as IdxSize).collect());
+    let mut out = df.take(&idx)?;
+    let millis = Int64Chunked::from_iter_values("ts".into(), kept.iter().map(|(_, b)| b.ts_event / NANOS_PER_MILLI));
+    out.with_column(millis_to_timestamp(time_column(df), millis)?)?;
+    let fields: [fn(&Bar) -> i64; 4] = [|b| b.open, |b| b.high, |b| b.low, |b| b.close];
+    for (name, field) in PRICE_COLUMNS.iter().zip(fields) {
+        let values = kept.iter().map(|(_, b)| field(b) as f64 / FIXED_POINT_SCALE as f64);
+        out.with_column(Float64Chunked::from_iter_values((*name).into(), values).into_series())?;
+    }
+    let report = report(df.height(), out.height(), violations);
+    Ok((out, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bar(minute: i64, close: i64, volume: u64) -> Bar {
+        Bar {
+            instrument_name: "CLF3".into(), instrument_id: 1, ts_event: minute * 60_000_000_000,
+            open: close, high: close + 10, low: close - 10, close, volume,
+        }
+    }
+
+    #[test]
+    fn test_classify_and_policies() {
+        let mut bars: Vec<Bar> = (0..20).map(|m| bar(m, 1_000, 1)).collect();
+        bars[3].low = 2_000; // low above high
+        bars[5].volume = 0;
+        bars[7].ts_event += 1_000; // off the minute grid
+        bars.push(bar(10, 1_000, 1)); // duplicate
+        bars.push(bar(2, 1_000, 1)); // duplicate and out of order
+        bars.push(bar(25, 9_000, 1)); // spike
+        bars.push(bar(21, 1_000, 1)); // out of order
+
+        let config = |policy| ValidationConfig { policy, ..Default::default() };
+        let (same, report) = validate_bars(bars.clone(), &config(ValidationPolicy::Report));
+        assert_eq!(same.len(), bars.len());
+        let count = |k| report.counts.get(&k).copied().unwrap_or(0);
+        assert_eq!(count(ViolationKind::OhlcInconsistent), 1);
+        assert_eq!(count(ViolationKind::ZeroVolume), 1);
+        assert_eq!(count(ViolationKind::Misaligned), 1);
+        assert_eq!(count(ViolationKind::DuplicateTimestamp), 2);
+        assert_eq!(count(ViolationKind::OutOfOrder), 1);
+        assert_eq!(count(ViolationKind::PriceSpike), 1);
+        assert!(report.enforce(ValidationPolicy::Fail).is_err());
+        assert!(serde_json::to_string(&report).unwrap().contains("\"price_spike\":1"));
+
+        let (dropped, _) = validate_bars(bars.clone(), &config(ValidationPolicy::Drop));
+        assert_eq!(dropped.len(), bars.len() - 7);
+
+        let (repaired, report) = validate_bars(bars, &config(ValidationPolicy::Repair));
+        assert_eq!(repaired.len(), 20); // minutes 0..=19 except 5, plus 21
+        assert!(repaired.windows(2).all(|w| w[0].ts_event < w[1].ts_event));
+        assert!(repaired.iter().all(|b| b.low <= b.high && b.ts_event % 60_000_000_000 == 0));
+        assert_eq!(report.kept, 20);
+    }
+
+    #[test]
+    fn test_dataframe_repair_keeps_columns() {
+        let bars = vec![bar(1, 1_000, 1), bar(0, 1_000, 1), bar(1, 1_000, 1)];
+        let df = crate::resampler::bars_to_dataframe(&bars).unwrap();
+        let config = ValidationConfig { policy: ValidationPolicy::Repair, ..Default::default() };
+        let (out, report) = validate_dataframe(&df, &config).unwrap();
+        assert_eq!(out.height(), 2);
+        assert_eq!(report.violations.len(), 2); // one out-of-order, one duplicate
+        assert_eq!(timestamp_millis(&out).unwrap().into_no_null_iter().collect::<Vec<_>>(), vec![0, 60_000]);
+        assert!(out.column(crate::resampler::INSTRUMENT_NAME_COL).is_ok());
+    }
+}
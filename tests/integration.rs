@@ -13,4 +13,6 @@ fn pipeline_smoke() {
     let window = ContractWindow { name: "test".into(), df: read, start_ts: 0, end_ts: i64::MAX };
     let stitched = stitcher::stitch_contracts(&[window]).unwrap();
     assert_eq!(df5.height(), stitched.height());
+    let symbols = stitched.column("instrument_name").unwrap().str().unwrap();
+    assert!(symbols.into_no_null_iter().all(|s| s == "CLF3"));
 }